@@ -0,0 +1,98 @@
+//! the scenarios more than one bench runs, so each is measured the same way wherever it turns up
+
+// each bench only runs some of them
+#![allow(dead_code)]
+
+use std::{hint::black_box, thread, time::Instant};
+
+use ring_buffer::RingBuffer;
+
+pub fn report(name: &str, start: Instant, elements: u64) {
+    let elapsed = start.elapsed();
+    println!(
+        "{name:<28} {:>8.2} ns/element",
+        elapsed.as_nanos() as f64 / elements as f64
+    );
+}
+
+pub fn two_threads_mpmc(elements: u64) {
+    let queue = RingBuffer::<u64, 1024>::new();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for x in 0..elements {
+                while queue.try_insert(x).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut received = 0;
+        while received < elements {
+            match queue.try_get() {
+                Some(v) => {
+                    black_box(v);
+                    received += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+    });
+    report("two threads, mpmc", start, elements);
+}
+
+pub fn two_threads_spsc(elements: u64) {
+    let mut queue = RingBuffer::<u64, 1024>::new();
+    let (mut producer, mut consumer) = queue.split();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for x in 0..elements {
+                while producer.try_insert(x).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut received = 0;
+        while received < elements {
+            match consumer.try_get() {
+                Some(v) => {
+                    black_box(v);
+                    received += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+    });
+    report("two threads, spsc", start, elements);
+}
+
+/// `threads` producers and as many consumers, none of which ever yields, moving `elements`
+/// elements between them
+pub fn contended(threads: u64, elements: u64) {
+    let queue = RingBuffer::<u64, 1024>::new();
+    let per_thread = elements / threads;
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for x in 0..per_thread {
+                    while queue.try_insert(x).is_err() {}
+                }
+            });
+            scope.spawn(|| {
+                let mut received = 0;
+                while received < per_thread {
+                    if let Some(v) = queue.try_get() {
+                        black_box(v);
+                        received += 1;
+                    }
+                }
+            });
+        }
+    });
+    report(
+        &format!("{threads} producers, {threads} consumers"),
+        start,
+        elements,
+    );
+}
@@ -0,0 +1,21 @@
+//! many producers and consumers hammering one buffer's counters at once, to compare with and
+//! without backing off after a lost compare-exchange
+//!
+//! run with `cargo bench --bench contention`, then again with
+//! `cargo bench --bench contention --no-default-features --features std` for the same loops
+//! without backoff
+
+mod common;
+
+use std::thread;
+
+const ELEMENTS: u64 = 4_000_000;
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(2, |n| n.get() as u64);
+    let mut threads = 1;
+    while threads * 2 <= cores.max(2) {
+        common::contended(threads, ELEMENTS);
+        threads *= 2;
+    }
+}
@@ -0,0 +1,55 @@
+//! what keeping the counters on cache lines of their own is worth, measured on the buffers
+//! themselves: streams through a `RingBuffer`, its split handles and a `ByteRing`, then producers
+//! and consumers hammering one `RingBuffer` at once
+//!
+//! run with `cargo bench --bench layout`, then again with
+//! `RUSTFLAGS="--cfg ring_buffer_unpadded" cargo bench --bench layout` for the same buffers with
+//! their counters packed together as they were before the padding went in
+
+mod common;
+
+use std::{hint::black_box, thread, time::Instant};
+
+use ring_buffer::ByteRing;
+
+const ELEMENTS: u64 = 10_000_000;
+
+fn two_threads_bytes() {
+    let mut ring = ByteRing::<4096>::new();
+    let (mut writer, mut reader) = ring.split();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut sent = 0;
+            while sent < ELEMENTS {
+                let n = writer.write(&[0; 64][..(ELEMENTS - sent).min(64) as usize]);
+                if n == 0 {
+                    thread::yield_now();
+                }
+                sent += n as u64;
+            }
+        });
+        let mut buf = [0; 64];
+        let mut received = 0;
+        while received < ELEMENTS {
+            match reader.read(&mut buf) {
+                0 => thread::yield_now(),
+                n => received += n as u64,
+            }
+        }
+        black_box(buf);
+    });
+    common::report("two threads, bytes", start, ELEMENTS);
+}
+
+fn main() {
+    common::two_threads_mpmc(ELEMENTS);
+    common::two_threads_spsc(ELEMENTS);
+    two_threads_bytes();
+    let cores = thread::available_parallelism().map_or(2, |n| n.get() as u64);
+    let mut threads = 1;
+    while threads * 2 <= cores {
+        common::contended(threads, ELEMENTS);
+        threads *= 2;
+    }
+}
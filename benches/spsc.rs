@@ -0,0 +1,39 @@
+//! compares moving elements through the shared MPMC methods against the split SPSC handles
+//!
+//! run with `cargo bench --bench spsc`
+
+mod common;
+
+use std::{hint::black_box, time::Instant};
+
+use ring_buffer::RingBuffer;
+
+const ELEMENTS: u64 = 10_000_000;
+
+fn one_thread_mpmc() {
+    let queue = RingBuffer::<u64, 1024>::new();
+    let start = Instant::now();
+    for x in 0..ELEMENTS {
+        let _ = queue.try_insert(x);
+        black_box(queue.try_get());
+    }
+    common::report("one thread, mpmc", start, ELEMENTS);
+}
+
+fn one_thread_spsc() {
+    let mut queue = RingBuffer::<u64, 1024>::new();
+    let (mut producer, mut consumer) = queue.split();
+    let start = Instant::now();
+    for x in 0..ELEMENTS {
+        let _ = producer.try_insert(x);
+        black_box(consumer.try_get());
+    }
+    common::report("one thread, spsc", start, ELEMENTS);
+}
+
+fn main() {
+    one_thread_mpmc();
+    one_thread_spsc();
+    common::two_threads_mpmc(ELEMENTS);
+    common::two_threads_spsc(ELEMENTS);
+}
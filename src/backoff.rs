@@ -0,0 +1,38 @@
+//! exponential backoff for retry loops that lost a race, so that threads contending for the same
+//! counter spread out instead of hammering its cache line in lockstep
+
+#[cfg(not(feature = "loom"))]
+use crate::sync::spin_loop;
+
+pub(crate) struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    /// the longest run of spins, as a power of two
+    const SPIN_LIMIT: u32 = 6;
+
+    pub(crate) const fn new() -> Self {
+        Backoff { step: 0 }
+    }
+
+    /// spins twice as long as the last time, up to `2^SPIN_LIMIT` spins
+    pub(crate) fn spin(&mut self) {
+        // under loom every spin is a yield to the scheduler, which only multiplies the
+        // interleavings to explore without changing what can happen
+        #[cfg(not(feature = "loom"))]
+        for _ in 0..1 << self.step {
+            spin_loop();
+        }
+        if self.step < Self::SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// whether the spins have reached their longest, so that a caller who can wait some other
+    /// way should
+    #[cfg(feature = "std")]
+    pub(crate) fn is_completed(&self) -> bool {
+        self.step >= Self::SPIN_LIMIT
+    }
+}
@@ -0,0 +1,265 @@
+//! operations that claim a whole run of slots with one compare-exchange
+
+use core::mem::MaybeUninit;
+
+use crate::{
+    raw::{self, Slot},
+    RingBuffer,
+};
+
+/// a run of places claimed for writing. any that haven't been filled when it's dropped, because
+/// the source ran dry or panicked, are published empty so they don't hold up consumers.
+struct WriteClaim<'a, T> {
+    slots: &'a [Slot<T>],
+    next: usize,
+    end: usize,
+}
+
+impl<'a, T> WriteClaim<'a, T> {
+    fn fill(&mut self, v: T) {
+        unsafe { raw::slot(self.slots, self.next).publish(self.next, Some(v)) };
+        self.next = self.next.wrapping_add(1);
+    }
+}
+
+impl<'a, T> Drop for WriteClaim<'a, T> {
+    fn drop(&mut self) {
+        while self.next != self.end {
+            unsafe { raw::slot(self.slots, self.next).publish(self.next, None) };
+            self.next = self.next.wrapping_add(1);
+        }
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// inserts clones of as many of `items` as there is space for, claiming the slots for all of
+    /// them at once, and returns how many were inserted
+    pub fn try_insert_slice(&self, items: &[T]) -> usize
+    where
+        T: Clone,
+    {
+        self.try_insert_iter(items.iter().cloned())
+    }
+
+    /// inserts elements from `iter` while there is space for them, claiming the slots for all of
+    /// them at once, and returns how many were inserted. as many slots are claimed as the
+    /// iterator's size hint allows; pass `iter.by_ref()` to keep whatever didn't fit.
+    pub fn try_insert_iter<I: IntoIterator<Item = T>>(&self, iter: I) -> usize {
+        let mut iter = iter.into_iter();
+        let (lower, upper) = iter.size_hint();
+        let max = upper.unwrap_or(lower).max(1);
+        let (place, count) = raw::claim_write(&self.end, &self.data, max);
+        if count == 0 {
+            return 0;
+        }
+        let mut claim = WriteClaim {
+            slots: &self.data,
+            next: place,
+            end: place.wrapping_add(count),
+        };
+        let mut inserted = 0;
+        for v in iter.by_ref().take(count) {
+            claim.fill(v);
+            inserted += 1;
+        }
+        drop(claim);
+        // even if nothing went in, the slots published empty may have been holding consumers up
+        self.notify_published(count);
+        inserted
+    }
+
+    /// takes as many elements as are available and fit in `out`, claiming all their slots at
+    /// once. returns how many were taken, which are at the front of `out`.
+    pub fn try_get_into(&self, out: &mut [MaybeUninit<T>]) -> usize {
+        loop {
+            let (place, count) = raw::claim_read(&self.start, &self.data, out.len());
+            if count == 0 {
+                return 0;
+            }
+            let mut taken = 0;
+            for i in 0..count {
+                let place = place.wrapping_add(i);
+                if let Some(v) = unsafe { raw::slot(&self.data, place).take(place, N) } {
+                    out[taken].write(v);
+                    taken += 1;
+                }
+            }
+            self.notify_freed(count);
+            // if every slot claimed was published empty, look again
+            if taken > 0 {
+                return taken;
+            }
+        }
+    }
+
+    /// claims up to `n` elements at once and returns an iterator over them. the slots are handed
+    /// back to producers as the iterator moves past them, and any elements left when it's dropped
+    /// are dropped with it.
+    pub fn drain_up_to(&self, n: usize) -> DrainUpTo<'_, T, N> {
+        let (place, count) = raw::claim_read(&self.start, &self.data, n);
+        DrainUpTo {
+            buffer: self,
+            next: place,
+            end: place.wrapping_add(count),
+        }
+    }
+}
+
+pub struct DrainUpTo<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+    next: usize,
+    end: usize,
+}
+
+impl<'a, T, const N: usize> Iterator for DrainUpTo<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.next != self.end {
+            let place = self.next;
+            self.next = place.wrapping_add(1);
+            let v = unsafe { raw::slot(&self.buffer.data, place).take(place, N) };
+            self.buffer.notify_freed(1);
+            if v.is_some() {
+                return v;
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.end.wrapping_sub(self.next)))
+    }
+}
+
+impl<'a, T, const N: usize> Drop for DrainUpTo<'a, T, N> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn get_into_vec<const N: usize>(queue: &RingBuffer<u32, N>, max: usize) -> Vec<u32> {
+        let mut out = [MaybeUninit::uninit(); 16];
+        let taken = queue.try_get_into(&mut out[..max]);
+        out[..taken]
+            .iter()
+            .map(|v| unsafe { v.assume_init() })
+            .collect()
+    }
+
+    #[test]
+    fn insert_slice_across_wrap() {
+        let queue = RingBuffer::<u32, 4>::new();
+        assert_eq!(queue.try_insert_slice(&[0, 1, 2]), 3);
+        assert_eq!(get_into_vec(&queue, 2), [0, 1]);
+        assert_eq!(queue.try_insert_slice(&[3, 4, 5, 6, 7]), 3);
+        assert_eq!(queue.try_insert_slice(&[8]), 0);
+        assert_eq!(get_into_vec(&queue, 16), [2, 3, 4, 5]);
+        assert_eq!(get_into_vec(&queue, 16), []);
+    }
+
+    #[test]
+    fn insert_iter_keeps_the_rest() {
+        let queue = RingBuffer::<u32, 4>::new();
+        let mut iter = 0..10;
+        assert_eq!(queue.try_insert_iter(iter.by_ref()), 4);
+        assert_eq!(iter.next(), Some(4));
+        assert_eq!(queue.drain().collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn insert_iter_shorter_than_its_hint() {
+        struct Liar(u32);
+
+        impl Iterator for Liar {
+            type Item = u32;
+
+            fn next(&mut self) -> Option<u32> {
+                self.0 = self.0.checked_sub(1)?;
+                Some(self.0)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (4, Some(4))
+            }
+        }
+
+        let queue = RingBuffer::<u32, 4>::new();
+        assert_eq!(queue.try_insert_iter(Liar(2)), 2);
+        // the two places claimed for nothing are passed over
+        assert_eq!(queue.try_get(), Some(1));
+        assert_eq!(queue.try_get(), Some(0));
+        assert_eq!(queue.try_get(), None);
+        assert_eq!(queue.try_insert_slice(&[5, 6, 7, 8]), 4);
+        assert_eq!(queue.drain().collect::<Vec<_>>(), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn panicking_clone_leaves_buffer_usable() {
+        #[derive(Debug)]
+        struct Bomb(u32);
+
+        impl Clone for Bomb {
+            fn clone(&self) -> Self {
+                assert!(self.0 != 2, "boom");
+                Bomb(self.0)
+            }
+        }
+
+        let queue = RingBuffer::<Bomb, 4>::new();
+        let items = [Bomb(0), Bomb(1), Bomb(2), Bomb(3)];
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            queue.try_insert_slice(&items)
+        }));
+        assert!(result.is_err());
+        assert_eq!(queue.try_get().map(|b| b.0), Some(0));
+        assert_eq!(queue.try_get().map(|b| b.0), Some(1));
+        assert!(queue.try_get().is_none());
+        assert!(queue.try_insert(Bomb(4)).is_ok());
+        assert_eq!(queue.try_get().map(|b| b.0), Some(4));
+    }
+
+    #[test]
+    fn drain_up_to() {
+        let queue = RingBuffer::<u32, 8>::new();
+        assert_eq!(queue.try_insert_iter(0..6), 6);
+        assert_eq!(queue.drain_up_to(4).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        let mut drain = queue.drain_up_to(4);
+        assert_eq!(drain.next(), Some(4));
+        drop(drain);
+        assert_eq!(queue.try_get(), None);
+        assert_eq!(queue.try_insert_iter(0..8), 8);
+    }
+
+    #[test]
+    fn batches_across_threads() {
+        let queue = RingBuffer::<u32, 16>::new();
+        let n = 100_000;
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut iter = 0..n;
+                while !iter.is_empty() {
+                    if queue.try_insert_iter(iter.by_ref().take(5)) == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut x = 0;
+            while x < n {
+                let batch = get_into_vec(&queue, 7);
+                if batch.is_empty() {
+                    std::thread::yield_now();
+                }
+                for y in batch {
+                    assert_eq!(y, x);
+                    x += 1;
+                }
+            }
+        });
+    }
+}
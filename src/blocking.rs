@@ -0,0 +1,331 @@
+//! blocking operations that park the calling thread instead of spinning
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+    time::Duration,
+};
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+use crate::{backoff::Backoff, RingBuffer};
+
+/// A set of threads waiting for the buffer to change in some way, e.g. for space to free up.
+///
+/// Whoever makes the change calls `notify` afterwards, which is only a fence and a load when
+/// nobody is waiting.
+pub struct Waiters {
+    /// the number of threads that have registered and might be about to sleep
+    sleeping: AtomicUsize,
+    /// bumped under `lock` by every `notify` that finds someone registered, so that a waiter can
+    /// tell whether it missed one between its last attempt and going to sleep
+    epoch: AtomicUsize,
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+
+impl Waiters {
+    pub(crate) const fn new() -> Self {
+        Waiters {
+            sleeping: AtomicUsize::new(0),
+            epoch: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// calls `attempt` until it returns `Some`, sleeping in between until notified. gives up and
+    /// returns `None` once `deadline` has passed.
+    pub fn wait_until<R>(
+        &self,
+        mut attempt: impl FnMut() -> Option<R>,
+        deadline: Option<Instant>,
+    ) -> Option<R> {
+        loop {
+            if let Some(r) = attempt() {
+                return Some(r);
+            }
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            // pairs with the fence in `notify`: either this attempt sees the change, or the
+            // notifier sees us registered and bumps `epoch` before waking anyone
+            fence(Ordering::SeqCst);
+            let epoch = self.epoch.load(Ordering::Acquire);
+            // not under the lock, as a successful attempt notifies the waiters for the opposite
+            // change, and holding our lock while taking theirs would deadlock against them
+            let result = attempt();
+            let timed_out = result.is_none() && self.sleep(epoch, deadline);
+            self.sleeping.fetch_sub(1, Ordering::Relaxed);
+            if result.is_some() {
+                return result;
+            }
+            if timed_out {
+                return attempt();
+            }
+        }
+    }
+
+    /// sleeps until notified or `deadline` passes, unless `epoch` shows a notify came in since
+    /// it was read. returns whether the deadline passed.
+    fn sleep(&self, epoch: usize, deadline: Option<Instant>) -> bool {
+        let guard = self.lock.lock().unwrap();
+        if self.epoch.load(Ordering::Relaxed) != epoch {
+            return false;
+        }
+        match deadline {
+            None => {
+                drop(self.changed.wait(guard).unwrap());
+                false
+            }
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                drop(self.changed.wait_timeout(guard, timeout).unwrap());
+                Instant::now() >= deadline
+            }
+        }
+    }
+
+    /// wakes every waiting thread, to be called after making the change they're waiting for
+    pub(crate) fn notify(&self) {
+        fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let guard = self.lock.lock().unwrap();
+            self.epoch.fetch_add(1, Ordering::Release);
+            drop(guard);
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// How a blocking operation waits between attempts, e.g. spinning for the lowest latency or
+/// parking to leave the CPU to other threads.
+pub trait WaitStrategy {
+    /// calls `attempt` until it returns `Some`, waiting in between. returns `None` if the
+    /// strategy gave up first.
+    ///
+    /// `waiters` are notified whenever the buffer changes in the way the caller is waiting for,
+    /// so a strategy that sleeps can sleep on them rather than polling.
+    fn wait_until<R>(self, waiters: &Waiters, attempt: impl FnMut() -> Option<R>) -> Option<R>;
+}
+
+/// Retries straight away with only a spin hint in between, never giving up the CPU.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BusySpin;
+
+impl WaitStrategy for BusySpin {
+    fn wait_until<R>(self, _: &Waiters, mut attempt: impl FnMut() -> Option<R>) -> Option<R> {
+        loop {
+            if let Some(r) = attempt() {
+                return Some(r);
+            }
+            spin_loop();
+        }
+    }
+}
+
+/// Spins between attempts, twice as long each time, then yields the thread once the spins get
+/// long enough that another thread might as well run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpinThenYield;
+
+impl WaitStrategy for SpinThenYield {
+    fn wait_until<R>(self, _: &Waiters, mut attempt: impl FnMut() -> Option<R>) -> Option<R> {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(r) = attempt() {
+                return Some(r);
+            }
+            if backoff.is_completed() {
+                std::thread::yield_now();
+            } else {
+                backoff.spin();
+            }
+        }
+    }
+}
+
+/// Parks the thread until the buffer changes, as `insert` and `get` do.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Park;
+
+impl WaitStrategy for Park {
+    fn wait_until<R>(self, waiters: &Waiters, attempt: impl FnMut() -> Option<R>) -> Option<R> {
+        waiters.wait_until(attempt, None)
+    }
+}
+
+/// Parks the thread like [`Park`], but gives up once the duration has passed since the wait
+/// began, as `insert_timeout` and `get_timeout` do.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout(pub Duration);
+
+impl WaitStrategy for Timeout {
+    fn wait_until<R>(self, waiters: &Waiters, attempt: impl FnMut() -> Option<R>) -> Option<R> {
+        waiters.wait_until(attempt, Some(Instant::now() + self.0))
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// inserts `v`, blocking until there is space for it
+    pub fn insert(&self, v: T) {
+        if self.insert_with(v, Park).is_err() {
+            unreachable!("parking never gives up");
+        }
+    }
+
+    /// takes the oldest element, blocking until there is one
+    pub fn get(&self) -> T {
+        self.get_with(Park).unwrap()
+    }
+
+    /// inserts `v`, blocking for at most `timeout` for there to be space for it. gives `v` back if
+    /// the buffer was still full when the timeout expired.
+    pub fn insert_timeout(&self, v: T, timeout: Duration) -> Result<(), T> {
+        self.insert_with(v, Timeout(timeout))
+    }
+
+    /// takes the oldest element, blocking for at most `timeout` for there to be one
+    pub fn get_timeout(&self, timeout: Duration) -> Option<T> {
+        self.get_with(Timeout(timeout))
+    }
+
+    /// inserts `v`, waiting for space the way `strategy` does. gives `v` back if the strategy
+    /// gave up while the buffer was still full.
+    pub fn insert_with<W: WaitStrategy>(&self, v: T, strategy: W) -> Result<(), T> {
+        let mut v = Some(v);
+        match strategy.wait_until(&self.not_full, || {
+            self.try_insert(v.take().unwrap())
+                .map_err(|e| v = Some(e))
+                .ok()
+        }) {
+            Some(()) => Ok(()),
+            None => Err(v.unwrap()),
+        }
+    }
+
+    /// takes the oldest element, waiting for one the way `strategy` does. returns `None` if the
+    /// strategy gave up while the buffer was still empty.
+    pub fn get_with<W: WaitStrategy>(&self, strategy: W) -> Option<T> {
+        strategy.wait_until(&self.not_empty, || self.try_get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocking_count() {
+        let queue = RingBuffer::<u32, 4>::new();
+        let n = 100_000;
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for x in 0..n {
+                    queue.insert(x);
+                }
+            });
+            for x in 0..n {
+                assert_eq!(queue.get(), x);
+            }
+        });
+    }
+
+    #[test]
+    fn blocking_many_producers_many_consumers() {
+        let queue = RingBuffer::<u64, 8>::new();
+        let n = 10_000;
+        let total = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for p in 0..3 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..n {
+                        queue.insert(i * 3 + p);
+                    }
+                });
+            }
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    for _ in 0..n {
+                        total.fetch_add(queue.get() as usize, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let n = 3 * n as usize;
+        assert_eq!(total.load(Ordering::Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn blocking_both_ways_on_two_slots() {
+        // with two slots, producers and consumers are both asleep much of the time, each waking
+        // the other side from inside their own attempts
+        let queue = RingBuffer::<u64, 2>::new();
+        let n = 5_000;
+        let total = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for p in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..n {
+                        queue.insert(i * 4 + p);
+                    }
+                });
+            }
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..n {
+                        total.fetch_add(queue.get() as usize, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let n = 4 * n as usize;
+        assert_eq!(total.load(Ordering::Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn timeouts() {
+        let queue = RingBuffer::<u32, 2>::new();
+        assert_eq!(queue.get_timeout(Duration::from_millis(10)), None);
+        assert!(queue.insert_timeout(1, Duration::from_millis(10)).is_ok());
+        assert!(queue.insert_timeout(2, Duration::from_millis(10)).is_ok());
+        assert_eq!(queue.insert_timeout(3, Duration::from_millis(10)), Err(3));
+        assert_eq!(queue.get_timeout(Duration::from_millis(10)), Some(1));
+    }
+
+    #[test]
+    fn every_strategy_counts() {
+        fn count<W: WaitStrategy + Copy + Sync>(strategy: W) {
+            let queue = RingBuffer::<u32, 4>::new();
+            let n = 1_000;
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    for x in 0..n {
+                        assert!(queue.insert_with(x, strategy).is_ok());
+                    }
+                });
+                for x in 0..n {
+                    assert_eq!(queue.get_with(strategy), Some(x));
+                }
+            });
+        }
+        count(BusySpin);
+        count(SpinThenYield);
+        count(Park);
+        count(Timeout(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn timeout_woken_in_time() {
+        let queue = RingBuffer::<u32, 2>::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                queue.insert(7);
+            });
+            assert_eq!(queue.get_timeout(Duration::from_secs(10)), Some(7));
+        });
+    }
+}
@@ -0,0 +1,438 @@
+//! a ring whose every element is seen by every subscribed reader, each following it with a cursor
+//! of its own
+
+use core::fmt;
+
+use crate::{
+    padded::CachePadded,
+    retained::Retained,
+    sync::{fence, spin_loop, AtomicUsize, Ordering},
+};
+
+/// A bounded multi-producer queue that every subscribed [`Reader`] reads all of, for up to `R`
+/// readers at once.
+///
+/// Elements stay in their slots until they're overwritten, and readers get clones of them. By
+/// default a writer waits for the slowest reader, failing to insert while it is `N` elements
+/// behind; in lossy mode writers go ahead and overwrite, and a reader that was overtaken is told
+/// how many elements it missed.
+pub struct Broadcast<T, const N: usize, const R: usize> {
+    /// the position of the next slot to be written, plus k * N
+    end: CachePadded<AtomicUsize>,
+    slots: [Slot<T>; N],
+    /// bit i is set while `cursors[i]` belongs to a reader
+    subscribed: AtomicUsize,
+    /// the position of the next element each reader will read
+    cursors: [CachePadded<AtomicUsize>; R],
+    /// whether writers overwrite elements that slow readers haven't got to, rather than waiting
+    lossy: bool,
+}
+
+struct Slot<T> {
+    /// `place` while the element for `place` is being written, `place + 1` once it's published
+    stamp: AtomicUsize,
+    /// how many readers are looking at the slot, which a writer waits out before overwriting it
+    active: AtomicUsize,
+    /// readers clone the element rather than taking it, so it stays until it's overwritten
+    value: Retained<T>,
+}
+
+unsafe impl<T: Send, const N: usize, const R: usize> Send for Broadcast<T, N, R> {}
+// readers clone elements through shared references, possibly several at once
+unsafe impl<T: Send + Sync, const N: usize, const R: usize> Sync for Broadcast<T, N, R> {}
+
+impl<T> Slot<T> {
+    /// a slot that looks like the element for `place` was published into it
+    #[cfg(not(feature = "loom"))]
+    const fn new(place: usize) -> Self {
+        Slot {
+            stamp: AtomicUsize::new(place.wrapping_add(1)),
+            active: AtomicUsize::new(0),
+            value: Retained::new(),
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    fn new(place: usize) -> Self {
+        Slot {
+            stamp: AtomicUsize::new(place.wrapping_add(1)),
+            active: AtomicUsize::new(0),
+            value: Retained::new(),
+        }
+    }
+}
+
+impl<T, const N: usize, const R: usize> Broadcast<T, N, R> {
+    /// see `RingBuffer::CHECK_CAPACITY`. the readers are tracked with one bit each in a usize.
+    const CHECK_CAPACITY: () = assert!(
+        N > 1 && N.is_power_of_two() && R <= usize::BITS as usize,
+        "a Broadcast's capacity must be a power of two greater than one, with at most \
+         usize::BITS readers"
+    );
+
+    /// a broadcast ring whose writers wait for the slowest reader
+    #[cfg(not(feature = "loom"))]
+    pub const fn new() -> Self {
+        Self::with_policy(false)
+    }
+
+    /// a broadcast ring whose writers overwrite elements that slow readers haven't got to
+    #[cfg(not(feature = "loom"))]
+    pub const fn new_lossy() -> Self {
+        Self::with_policy(true)
+    }
+
+    #[cfg(not(feature = "loom"))]
+    const fn with_policy(lossy: bool) -> Self {
+        let () = Self::CHECK_CAPACITY;
+        let mut slots = [const { core::mem::MaybeUninit::<Slot<T>>::uninit() }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i] = core::mem::MaybeUninit::new(Slot::new(i.wrapping_sub(N)));
+            i += 1;
+        }
+        Broadcast {
+            end: CachePadded::new(AtomicUsize::new(0)),
+            slots: unsafe { (&slots as *const _ as *const [Slot<T>; N]).read() },
+            subscribed: AtomicUsize::new(0),
+            cursors: [const { CachePadded::new(AtomicUsize::new(0)) }; R],
+            lossy,
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new() -> Self {
+        Self::with_policy(false)
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new_lossy() -> Self {
+        Self::with_policy(true)
+    }
+
+    #[cfg(feature = "loom")]
+    fn with_policy(lossy: bool) -> Self {
+        let () = Self::CHECK_CAPACITY;
+        Broadcast {
+            end: CachePadded::new(AtomicUsize::new(0)),
+            slots: core::array::from_fn(|i| Slot::new(i.wrapping_sub(N))),
+            subscribed: AtomicUsize::new(0),
+            cursors: core::array::from_fn(|_| CachePadded::new(AtomicUsize::new(0))),
+            lossy,
+        }
+    }
+
+    fn slot(&self, place: usize) -> &Slot<T> {
+        &self.slots[place & (N - 1)]
+    }
+
+    /// adds a reader that will see every element inserted from now on, or returns `None` if there
+    /// are already `R` of them
+    pub fn subscribe(&self) -> Option<Reader<'_, T, N, R>> {
+        let mut subscribed = self.subscribed.load(Ordering::Relaxed);
+        let index = loop {
+            let index = (!subscribed).trailing_zeros() as usize;
+            if index >= R {
+                return None;
+            }
+            match self.subscribed.compare_exchange_weak(
+                subscribed,
+                subscribed | 1 << index,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break index,
+                Err(current) => subscribed = current,
+            }
+        };
+        // a writer that read the cursor before it was ours may still claim the place at the
+        // `end` it saw then. with `end` unchanged around the store, that's at most `cursor`, so
+        // no such writer can get a whole lap ahead of the new reader.
+        let cursor = loop {
+            let cursor = self.end.load(Ordering::SeqCst);
+            self.cursors[index].store(cursor, Ordering::SeqCst);
+            if self.end.load(Ordering::SeqCst) == cursor {
+                break cursor;
+            }
+        };
+        Some(Reader {
+            broadcast: self,
+            index,
+            cursor,
+        })
+    }
+
+    /// how many readers are subscribed
+    pub fn reader_count(&self) -> usize {
+        self.subscribed.load(Ordering::Relaxed).count_ones() as usize
+    }
+
+    /// whether every reader has read the element at `place`
+    fn readers_past(&self, place: usize) -> bool {
+        let mut subscribed = self.subscribed.load(Ordering::SeqCst);
+        while subscribed != 0 {
+            let index = subscribed.trailing_zeros() as usize;
+            subscribed &= subscribed - 1;
+            let cursor = self.cursors[index].load(Ordering::SeqCst);
+            if (cursor.wrapping_sub(place) as isize) <= 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// inserts `v` for every reader to see. fails if the slowest reader hasn't read the element
+    /// it would overwrite, unless the ring is lossy, or if the writer of that element hasn't
+    /// finished.
+    pub fn try_insert(&self, v: T) -> Result<(), T> {
+        let mut place = self.end.load(Ordering::SeqCst);
+        loop {
+            let previous = place.wrapping_sub(N);
+            if self.slot(place).stamp.load(Ordering::Acquire) != previous.wrapping_add(1)
+                || !(self.lossy || self.readers_past(previous))
+            {
+                let current = self.end.load(Ordering::SeqCst);
+                if current == place {
+                    return Err(v);
+                }
+                // another writer claimed this place since we loaded `end`
+                place = current;
+                continue;
+            }
+            match self.end.compare_exchange_weak(
+                place,
+                place.wrapping_add(1),
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => place = current,
+            }
+        }
+        let slot = self.slot(place);
+        // readers that see this stamp know the old element is gone, and a lossy writer waits for
+        // any that got in first to finish cloning it
+        slot.stamp.store(place, Ordering::Relaxed);
+        // pairs with the fence in `Reader::try_get`: either the reader sees the new stamp, or
+        // this sees the reader
+        fence(Ordering::SeqCst);
+        while slot.active.load(Ordering::Acquire) != 0 {
+            spin_loop();
+        }
+        unsafe { slot.value.replace(v) };
+        slot.stamp.store(place.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T, const N: usize, const R: usize> Default for Broadcast<T, N, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One subscriber to a [`Broadcast`], from [`Broadcast::subscribe`].
+pub struct Reader<'a, T, const N: usize, const R: usize> {
+    broadcast: &'a Broadcast<T, N, R>,
+    /// which of the broadcast's cursors is ours
+    index: usize,
+    cursor: usize,
+}
+
+/// keeps a writer from overwriting a slot while it's being looked at, even if cloning panics
+struct Active<'s>(&'s AtomicUsize);
+
+impl<'s> Drop for Active<'s> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: Clone, const N: usize, const R: usize> Reader<'a, T, N, R> {
+    /// returns a clone of the next element. in lossy mode, if writers have overwritten it, moves
+    /// on to the oldest element left and says how many were missed.
+    pub fn try_get(&mut self) -> Result<T, TryGetError> {
+        let slot = self.broadcast.slot(self.cursor);
+        let stamp = {
+            slot.active.fetch_add(1, Ordering::Relaxed);
+            let _active = Active(&slot.active);
+            fence(Ordering::SeqCst);
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == self.cursor.wrapping_add(1) {
+                let v = unsafe { slot.value.with(T::clone) };
+                drop(_active);
+                self.cursor = self.cursor.wrapping_add(1);
+                self.broadcast.cursors[self.index].store(self.cursor, Ordering::SeqCst);
+                return Ok(v);
+            }
+            stamp
+        };
+        if (stamp.wrapping_sub(self.cursor.wrapping_add(1)) as isize) < 0 {
+            return Err(TryGetError::Empty);
+        }
+        // the slot has moved on a lap, so skip to the oldest element that may still be there
+        let oldest = self.broadcast.end.load(Ordering::SeqCst).wrapping_sub(N);
+        let missed = oldest.wrapping_sub(self.cursor);
+        self.cursor = oldest;
+        self.broadcast.cursors[self.index].store(self.cursor, Ordering::SeqCst);
+        Err(TryGetError::Lagged(missed))
+    }
+}
+
+impl<'a, T, const N: usize, const R: usize> Drop for Reader<'a, T, N, R> {
+    fn drop(&mut self) {
+        self.broadcast
+            .subscribed
+            .fetch_and(!(1 << self.index), Ordering::SeqCst);
+    }
+}
+
+/// The error from [`Reader::try_get`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryGetError {
+    Empty,
+    /// writers to a lossy ring overwrote this many elements before the reader got to them
+    Lagged(usize),
+}
+
+impl fmt::Display for TryGetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryGetError::Empty => f.write_str("reading from an empty broadcast"),
+            TryGetError::Lagged(n) => write!(f, "reader lagged behind by {} elements", n),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryGetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn read_all<const N: usize, const R: usize>(reader: &mut Reader<'_, u32, N, R>) -> Vec<u32> {
+        core::iter::from_fn(|| reader.try_get().ok()).collect()
+    }
+
+    #[test]
+    fn every_reader_sees_everything() {
+        let broadcast = Broadcast::<u32, 4, 2>::new();
+        // nobody is subscribed, so this is never seen
+        assert!(broadcast.try_insert(0).is_ok());
+        let mut a = broadcast.subscribe().unwrap();
+        assert_eq!(a.try_get(), Err(TryGetError::Empty));
+        assert!(broadcast.try_insert(1).is_ok());
+        let mut b = broadcast.subscribe().unwrap();
+        assert!(broadcast.subscribe().is_none());
+        assert_eq!(broadcast.reader_count(), 2);
+        assert!(broadcast.try_insert(2).is_ok());
+        assert_eq!(read_all(&mut a), [1, 2]);
+        assert_eq!(read_all(&mut b), [2]);
+    }
+
+    #[test]
+    fn writers_wait_for_the_slowest_reader() {
+        let broadcast = Broadcast::<u32, 4, 2>::new();
+        let mut fast = broadcast.subscribe().unwrap();
+        let mut slow = broadcast.subscribe().unwrap();
+        for i in 0..4 {
+            assert!(broadcast.try_insert(i).is_ok());
+        }
+        assert_eq!(read_all(&mut fast), [0, 1, 2, 3]);
+        assert_eq!(broadcast.try_insert(4), Err(4));
+        assert_eq!(slow.try_get(), Ok(0));
+        assert!(broadcast.try_insert(4).is_ok());
+        assert_eq!(broadcast.try_insert(5), Err(5));
+        // a reader leaving stops holding writers up
+        drop(slow);
+        assert!(broadcast.try_insert(5).is_ok());
+        assert_eq!(read_all(&mut fast), [4, 5]);
+        assert_eq!(broadcast.reader_count(), 1);
+    }
+
+    #[test]
+    fn lossy_readers_lag() {
+        let broadcast = Broadcast::<u32, 4, 1>::new_lossy();
+        let mut reader = broadcast.subscribe().unwrap();
+        for i in 0..10 {
+            assert!(broadcast.try_insert(i).is_ok());
+        }
+        assert_eq!(reader.try_get(), Err(TryGetError::Lagged(6)));
+        assert_eq!(read_all(&mut reader), [6, 7, 8, 9]);
+        assert!(broadcast.try_insert(10).is_ok());
+        assert_eq!(reader.try_get(), Ok(10));
+    }
+
+    #[test]
+    fn overwritten_elements_are_dropped() {
+        let value = std::sync::Arc::new(());
+        let broadcast = Broadcast::<std::sync::Arc<()>, 2, 1>::new();
+        for _ in 0..3 {
+            assert!(broadcast.try_insert(value.clone()).is_ok());
+        }
+        assert_eq!(std::sync::Arc::strong_count(&value), 3);
+        drop(broadcast);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn broadcast_across_threads() {
+        let broadcast = Broadcast::<u64, 8, 3>::new();
+        let n = 10_000;
+        let readers: Vec<_> = (0..3).map(|_| broadcast.subscribe().unwrap()).collect();
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let broadcast = &broadcast;
+                scope.spawn(move || {
+                    for i in 0..n / 2 {
+                        while broadcast.try_insert(i * 2 + p).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            for mut reader in readers {
+                scope.spawn(move || {
+                    let mut sum = 0;
+                    for _ in 0..n {
+                        loop {
+                            match reader.try_get() {
+                                Ok(v) => break sum += v,
+                                Err(TryGetError::Empty) => std::thread::yield_now(),
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                    assert_eq!(sum, n * (n - 1) / 2);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn lossy_across_threads() {
+        let broadcast = Broadcast::<u64, 4, 1>::new_lossy();
+        let n = 100_000;
+        let mut reader = broadcast.subscribe().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..n {
+                    assert!(broadcast.try_insert(i).is_ok());
+                }
+            });
+            let mut next = 0;
+            while next < n {
+                match reader.try_get() {
+                    Ok(v) => {
+                        assert_eq!(v, next);
+                        next += 1;
+                    }
+                    Err(TryGetError::Lagged(missed)) => next += missed as u64,
+                    Err(TryGetError::Empty) => std::thread::yield_now(),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,521 @@
+//! a single-producer single-consumer byte stream that hands out contiguous slices of its storage,
+//! for I/O that wants to read or write whole regions at once, like serial ports and DMA
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+};
+
+use crate::{
+    padded::CachePadded,
+    sync::{AtomicUsize, Ordering},
+};
+
+/// A bounded byte stream between one writer and one reader, which lend out the largest
+/// contiguous region of the buffer they can use instead of copying byte by byte.
+///
+/// It uses the same `start` and `end` counters as [`RingBuffer`](crate::RingBuffer), but with
+/// only one writer and one reader no slot stamps are needed: everything between the counters is
+/// readable, and everything else is writable. Like a bip buffer, when there is more room at the
+/// beginning of the storage than is left before the wrap, the writer is lent the beginning
+/// instead, and committing to it leaves the rest of the lap unused until the reader has passed
+/// it. Otherwise a region that runs into the end of the storage is cut short there.
+pub struct ByteRing<const N: usize> {
+    /// the position of the next byte to be read, plus k * N
+    start: CachePadded<AtomicUsize>,
+    /// the position of the next byte to be written, plus k * N
+    end: CachePadded<AtomicUsize>,
+    /// where the writer last skipped the rest of the lap to write at the beginning of the
+    /// storage. only meaningful to a reader that hasn't passed it and can see `end` beyond the
+    /// lap, since the writer can't skip again until the reader has.
+    skipped_from: AtomicUsize,
+    /// the bytes are only touched through the split handles, each in the region the counters
+    /// give it
+    data: UnsafeCell<[u8; N]>,
+    /// a cell for each byte, which a region is checked out of before it's lent, so that loom
+    /// sees which bytes each handle touches. one cell for the whole storage would have the
+    /// writer and the reader racing whenever both held a region, however far apart.
+    #[cfg(feature = "loom")]
+    cells: [crate::sync::UnsafeCell<()>; N],
+}
+
+unsafe impl<const N: usize> Sync for ByteRing<N> {}
+
+impl<const N: usize> ByteRing<N> {
+    /// see `RingBuffer::CHECK_CAPACITY`
+    const CHECK_CAPACITY: () = assert!(
+        N > 1 && N.is_power_of_two(),
+        "a ByteRing's capacity must be a power of two greater than one"
+    );
+
+    #[cfg(not(feature = "loom"))]
+    pub const fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        ByteRing {
+            start: CachePadded::new(AtomicUsize::new(0)),
+            end: CachePadded::new(AtomicUsize::new(0)),
+            skipped_from: AtomicUsize::new(0),
+            data: UnsafeCell::new([0; N]),
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        ByteRing {
+            start: CachePadded::new(AtomicUsize::new(0)),
+            end: CachePadded::new(AtomicUsize::new(0)),
+            skipped_from: AtomicUsize::new(0),
+            data: UnsafeCell::new([0; N]),
+            cells: core::array::from_fn(|_| crate::sync::UnsafeCell::new(())),
+        }
+    }
+
+    /// splits the ring into its writing and reading halves. anything left unread when they're
+    /// dropped is still there the next time it's split.
+    pub fn split(&mut self) -> (ByteWriter<'_, N>, ByteReader<'_, N>) {
+        let ring = &*self;
+        (
+            ByteWriter {
+                ring,
+                start: Cell::new(ring.start.load(Ordering::Acquire)),
+                skip: 0,
+            },
+            ByteReader {
+                ring,
+                end: Cell::new(ring.end.load(Ordering::Acquire)),
+            },
+        )
+    }
+
+    /// the `len` bytes of storage starting at position `place`, which mustn't run past the end
+    ///
+    /// # Safety
+    /// nobody may be writing any of those bytes while the slice is alive
+    unsafe fn region(&self, place: usize, len: usize) -> &[u8] {
+        let offset = place & (N - 1);
+        debug_assert!(offset + len <= N);
+        #[cfg(feature = "loom")]
+        for cell in &self.cells[offset..offset + len] {
+            cell.with(drop);
+        }
+        core::slice::from_raw_parts((self.data.get() as *const u8).add(offset), len)
+    }
+}
+
+impl<const N: usize> Default for ByteRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The writing half of a split [`ByteRing`].
+pub struct ByteWriter<'a, const N: usize> {
+    ring: &'a ByteRing<N>,
+    /// the last `start` seen, which the reader can only have moved on from. going by it saves
+    /// pulling the reader's cache line over until the space it leaves isn't enough.
+    start: Cell<usize>,
+    /// how much of the lap the last `write_slice` passed over to lend out the beginning of the
+    /// storage, which committing to it skips
+    skip: usize,
+}
+
+impl<'a, const N: usize> ByteWriter<'a, N> {
+    /// how many bytes could be written right now, though maybe not contiguously
+    pub fn free(&self) -> usize {
+        self.free_for(N)
+    }
+
+    /// how many bytes can be written, going by the cached `start` unless that leaves less than
+    /// `wanted`
+    fn free_for(&self, wanted: usize) -> usize {
+        let end = self.ring.end.load(Ordering::Relaxed);
+        let free = N - end.wrapping_sub(self.start.get());
+        if free >= wanted {
+            return free;
+        }
+        self.start.set(self.ring.start.load(Ordering::Acquire));
+        N - end.wrapping_sub(self.start.get())
+    }
+
+    /// the largest contiguous region that can be written right now, which is empty if the ring
+    /// is full. if there's more room at the beginning of the storage than is left before the
+    /// wrap, that's the region lent out, and committing any of it skips the rest of the lap.
+    /// nothing written there is seen by the reader until it's committed.
+    pub fn write_slice(&mut self) -> &mut [u8] {
+        let end = self.ring.end.load(Ordering::Relaxed);
+        let until_wrap = self.until_wrap();
+        // going by the cached `start` unless it says the beginning doesn't beat the rest of the
+        // lap, when the reader may since have freed enough that it does
+        let free = self.free_for((2 * until_wrap + 1).min(N));
+        let (skip, len) = match free.saturating_sub(until_wrap) {
+            front if front > until_wrap => (until_wrap, front),
+            _ => (0, free.min(until_wrap)),
+        };
+        self.skip = skip;
+        unsafe { self.region_mut(end.wrapping_add(skip), len) }
+    }
+
+    /// the region `write_slice` would lend if it never skipped to the beginning of the storage,
+    /// which is empty if there's no room before the wrap
+    pub(crate) fn tail_slice(&mut self) -> &mut [u8] {
+        let end = self.ring.end.load(Ordering::Relaxed);
+        let until_wrap = self.until_wrap();
+        let len = self.free_for(until_wrap).min(until_wrap);
+        self.skip = 0;
+        unsafe { self.region_mut(end, len) }
+    }
+
+    /// how many bytes there are between the write position and the end of the storage, which
+    /// the `tail_slice` is as long as unless the reader is in the way
+    pub(crate) fn until_wrap(&self) -> usize {
+        N - (self.ring.end.load(Ordering::Relaxed) & (N - 1))
+    }
+
+    /// the `len` bytes of storage starting at position `place`, for the writer to fill in
+    ///
+    /// # Safety
+    /// the bytes must be free, so that the reader isn't looking at any of them
+    unsafe fn region_mut(&mut self, place: usize, len: usize) -> &mut [u8] {
+        let offset = place & (N - 1);
+        debug_assert!(offset + len <= N);
+        #[cfg(feature = "loom")]
+        for cell in &self.ring.cells[offset..offset + len] {
+            cell.with_mut(drop);
+        }
+        core::slice::from_raw_parts_mut((self.ring.data.get() as *mut u8).add(offset), len)
+    }
+
+    /// hands the first `n` bytes of the last `write_slice` over to the reader, or of the region
+    /// before the wrap if there wasn't one
+    ///
+    /// # Panics
+    /// if `n` is longer than that region
+    pub fn commit(&mut self, n: usize) {
+        let end = self.ring.end.load(Ordering::Relaxed);
+        let skip = core::mem::take(&mut self.skip);
+        let writable = match skip {
+            0 => self.free_for(n).min(self.until_wrap()),
+            skip => self.free_for(skip + n).saturating_sub(skip),
+        };
+        assert!(n <= writable, "committed more bytes than were writable");
+        if n == 0 {
+            return;
+        }
+        if skip > 0 {
+            // released along with `end` below
+            self.ring.skipped_from.store(end, Ordering::Relaxed);
+        }
+        self.ring
+            .end
+            .store(end.wrapping_add(skip + n), Ordering::Release);
+    }
+
+    /// copies as much of `buf` in as there is room for, across the wrap if need be, and returns
+    /// how many bytes were written
+    pub fn write(&mut self, mut buf: &[u8]) -> usize {
+        let mut written = 0;
+        // at most two regions: up to the end of the storage, then from its beginning
+        for _ in 0..2 {
+            let region = self.tail_slice();
+            let n = region.len().min(buf.len());
+            region[..n].copy_from_slice(&buf[..n]);
+            self.commit(n);
+            written += n;
+            buf = &buf[n..];
+        }
+        written
+    }
+}
+
+/// Writes the whole string or, if there isn't room for it all, none of it.
+impl<'a, const N: usize> fmt::Write for ByteWriter<'a, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > self.free() {
+            return Err(fmt::Error);
+        }
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Fails with `WouldBlock` rather than writing nothing when the ring is full.
+#[cfg(feature = "std")]
+impl<'a, const N: usize> std::io::Write for ByteWriter<'a, N> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match ByteWriter::write(self, buf) {
+            0 if !buf.is_empty() => Err(std::io::ErrorKind::WouldBlock.into()),
+            n => Ok(n),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reading half of a split [`ByteRing`].
+pub struct ByteReader<'a, const N: usize> {
+    ring: &'a ByteRing<N>,
+    /// the last `end` seen, the reader's counterpart to the writer's cached `start`
+    end: Cell<usize>,
+}
+
+impl<'a, const N: usize> ByteReader<'a, N> {
+    /// how many bytes could be read right now, though maybe not contiguously
+    pub fn available(&self) -> usize {
+        let start = self.ring.start.load(Ordering::Relaxed);
+        let available = self.available_for(N);
+        let until_wrap = N - (start & (N - 1));
+        match self.skip_at(start, available) {
+            Some(before) => available - (until_wrap - before),
+            None => available,
+        }
+    }
+
+    /// how far from `start` the writer skipped to the beginning of the storage, if it did before
+    /// the `available` bytes run out
+    fn skip_at(&self, start: usize, available: usize) -> Option<usize> {
+        let until_wrap = N - (start & (N - 1));
+        if available <= until_wrap {
+            return None;
+        }
+        // pairs with the release of `end`, which `available` came from
+        let before = self
+            .ring
+            .skipped_from
+            .load(Ordering::Relaxed)
+            .wrapping_sub(start);
+        (before < until_wrap).then_some(before)
+    }
+
+    /// how many bytes from `start` are skipped and how many readable ones follow them
+    /// contiguously
+    fn readable(&self) -> (usize, usize) {
+        let start = self.ring.start.load(Ordering::Relaxed);
+        let until_wrap = N - (start & (N - 1));
+        let available = self.available_for(until_wrap);
+        match self.skip_at(start, available) {
+            Some(0) => (until_wrap, available - until_wrap),
+            Some(before) => (0, before),
+            None => (0, available.min(until_wrap)),
+        }
+    }
+
+    /// how many bytes can be read, going by the cached `end` unless that gives less than `wanted`
+    fn available_for(&self, wanted: usize) -> usize {
+        let start = self.ring.start.load(Ordering::Relaxed);
+        let available = self.end.get().wrapping_sub(start);
+        if available >= wanted {
+            return available;
+        }
+        self.end.set(self.ring.end.load(Ordering::Acquire));
+        self.end.get().wrapping_sub(start)
+    }
+
+    /// the largest contiguous region that can be read right now, which is empty if the ring is
+    /// empty. it stays put until it's consumed.
+    pub fn read_slice(&self) -> &[u8] {
+        let start = self.ring.start.load(Ordering::Relaxed);
+        let (skip, len) = self.readable();
+        unsafe { self.ring.region(start.wrapping_add(skip), len) }
+    }
+
+    /// hands the first `n` bytes of the `read_slice` back to the writer
+    ///
+    /// # Panics
+    /// if `n` is longer than the `read_slice`
+    pub fn consume(&mut self, n: usize) {
+        let start = self.ring.start.load(Ordering::Relaxed);
+        let (skip, len) = self.readable();
+        assert!(n <= len, "consumed more bytes than were readable");
+        self.ring
+            .start
+            .store(start.wrapping_add(skip + n), Ordering::Release);
+    }
+
+    /// copies as many bytes out as are available and fit in `buf`, across the wrap if need be,
+    /// and returns how many were read
+    pub fn read(&mut self, mut buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        for _ in 0..2 {
+            let region = self.read_slice();
+            let n = region.len().min(buf.len());
+            buf[..n].copy_from_slice(&region[..n]);
+            self.consume(n);
+            read += n;
+            buf = &mut buf[n..];
+        }
+        read
+    }
+}
+
+/// Fails with `WouldBlock` rather than reading nothing when the ring is empty, since there's no
+/// end of the stream to report.
+#[cfg(feature = "std")]
+impl<'a, const N: usize> std::io::Read for ByteReader<'a, N> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match ByteReader::read(self, buf) {
+            0 if !buf.is_empty() => Err(std::io::ErrorKind::WouldBlock.into()),
+            n => Ok(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_slice_takes_the_larger_side_of_the_wrap() {
+        let mut ring = ByteRing::<8>::new();
+        let (mut writer, mut reader) = ring.split();
+        assert_eq!(writer.write_slice().len(), 8);
+        assert_eq!(reader.read_slice(), []);
+        writer.write_slice()[..6].copy_from_slice(b"abcdef");
+        writer.commit(6);
+        assert_eq!(writer.write_slice().len(), 2);
+        assert_eq!(reader.read_slice(), b"abcdef");
+        reader.consume(4);
+        // 2 bytes before the wrap and 4 after it, so the beginning is lent out, but committing
+        // none of it doesn't skip anything
+        assert_eq!(writer.free(), 6);
+        assert_eq!(writer.write_slice().len(), 4);
+        writer.commit(0);
+        assert_eq!(writer.free(), 6);
+        writer.write_slice().copy_from_slice(b"ghij");
+        writer.commit(4);
+        // the 2 bytes passed over are still taken until the reader gets past them
+        assert_eq!(writer.free(), 0);
+        assert_eq!(reader.available(), 6);
+        assert_eq!(reader.read_slice(), b"ef");
+        reader.consume(2);
+        assert_eq!(reader.read_slice(), b"ghij");
+        assert_eq!(writer.free(), 2);
+        reader.consume(1);
+        // now there's more before the wrap than after it
+        assert_eq!(writer.write_slice().len(), 4);
+        writer.write_slice().copy_from_slice(b"klmn");
+        writer.commit(4);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf), 7);
+        assert_eq!(&buf[..7], b"hijklmn");
+    }
+
+    #[test]
+    fn copy_across_the_wrap() {
+        let mut ring = ByteRing::<8>::new();
+        let (mut writer, mut reader) = ring.split();
+        let mut buf = [0; 8];
+        for round in 0..5u8 {
+            let data = [round, round + 1, round + 2, round + 3, round + 4];
+            assert_eq!(writer.write(&data), 5);
+            assert_eq!(writer.write(&data), 3);
+            assert_eq!(writer.write(&data), 0);
+            assert_eq!(reader.read(&mut buf[..4]), 4);
+            assert_eq!(reader.read(&mut buf[4..]), 4);
+            assert_eq!(
+                buf,
+                [
+                    round,
+                    round + 1,
+                    round + 2,
+                    round + 3,
+                    round + 4,
+                    round,
+                    round + 1,
+                    round + 2
+                ]
+            );
+            assert_eq!(reader.read(&mut buf), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn commit_too_much() {
+        let mut ring = ByteRing::<8>::new();
+        let (mut writer, mut reader) = ring.split();
+        writer.commit(6);
+        reader.consume(6);
+        writer.commit(3);
+    }
+
+    #[test]
+    fn format_into() {
+        use core::fmt::Write;
+
+        let mut ring = ByteRing::<16>::new();
+        let (mut writer, mut reader) = ring.split();
+        assert!(write!(writer, "{}-ab", 12).is_ok());
+        assert!(writer.write_str("0123456789abc").is_err());
+        let mut buf = [0; 16];
+        let n = reader.read(&mut buf);
+        assert_eq!(&buf[..n], b"12-ab");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_traits() {
+        use std::io::{ErrorKind, Read, Write};
+
+        let mut ring = ByteRing::<4>::new();
+        let (mut writer, mut reader) = ring.split();
+        let mut buf = [0; 4];
+        assert_eq!(
+            Read::read(&mut reader, &mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        assert_eq!(Write::write(&mut writer, b"hello").unwrap(), 4);
+        assert_eq!(
+            Write::write(&mut writer, b"o").unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        reader.read_exact(&mut buf[..2]).unwrap();
+        writer.write_all(b"o!").unwrap();
+        let mut rest = std::vec::Vec::new();
+        assert_eq!(
+            reader.read_to_end(&mut rest).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        assert_eq!(&buf[..2], b"he");
+        assert_eq!(rest, b"llo!");
+    }
+
+    #[test]
+    fn stream_across_threads() {
+        let mut ring = ByteRing::<64>::new();
+        let (mut writer, mut reader) = ring.split();
+        let n = 1_000_000;
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut x = 0usize;
+                while x < n {
+                    let region = writer.write_slice();
+                    let len = region.len().min(n - x).min(13);
+                    for byte in &mut region[..len] {
+                        *byte = x as u8;
+                        x += 1;
+                    }
+                    writer.commit(len);
+                    if len == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut x = 0usize;
+            while x < n {
+                let region = reader.read_slice();
+                let len = region.len();
+                for &byte in region {
+                    assert_eq!(byte, x as u8);
+                    x += 1;
+                }
+                reader.consume(len);
+                if len == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+}
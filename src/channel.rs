@@ -0,0 +1,361 @@
+//! owned sender and receiver handles sharing a buffer through an `Arc`, so that using it across
+//! threads doesn't need scoped threads or a `'static` buffer
+
+use alloc::sync::Arc;
+use core::fmt;
+
+use crate::{
+    sync::{AtomicUsize, Ordering},
+    RingBuffer,
+};
+
+struct Shared<T, const N: usize> {
+    buffer: RingBuffer<T, N>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+/// creates a channel backed by a `RingBuffer<T, N>`, returning its first sender and receiver
+pub fn channel<T, const N: usize>() -> (Sender<T, N>, Receiver<T, N>) {
+    let shared = Arc::new(Shared {
+        buffer: RingBuffer::new(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// The sending half of a [`channel`]. Cloning it adds another sender.
+pub struct Sender<T, const N: usize> {
+    shared: Arc<Shared<T, N>>,
+}
+
+/// The receiving half of a [`channel`]. Cloning it adds another receiver; each element goes to
+/// only one of them.
+pub struct Receiver<T, const N: usize> {
+    shared: Arc<Shared<T, N>>,
+}
+
+impl<T, const N: usize> Sender<T, N> {
+    pub fn try_send(&self, v: T) -> Result<(), TrySendError<T>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(TrySendError::Disconnected(v));
+        }
+        self.shared.buffer.try_insert(v).map_err(TrySendError::Full)
+    }
+
+    /// sends `v`, blocking until there is space for it. fails, giving `v` back, if every
+    /// receiver has been dropped.
+    #[cfg(feature = "std")]
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        let mut v = Some(v);
+        self.shared
+            .buffer
+            .not_full
+            .wait_until(
+                || match self.try_send(v.take().unwrap()) {
+                    Ok(()) => Some(Ok(())),
+                    Err(TrySendError::Disconnected(e)) => Some(Err(SendError(e))),
+                    Err(TrySendError::Full(e)) => {
+                        v = Some(e);
+                        None
+                    }
+                },
+                None,
+            )
+            .unwrap()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::Relaxed)
+    }
+
+    /// whether every receiver has been dropped, so nothing sent will ever be received
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.receivers.load(Ordering::Acquire) == 0
+    }
+}
+
+impl<T, const N: usize> Receiver<T, N> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(v) = self.shared.buffer.try_get() {
+            return Ok(v);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            // the last sender may have sent something just before it was dropped
+            return self
+                .shared
+                .buffer
+                .try_get()
+                .ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// takes the oldest element, blocking until there is one. fails once the channel is empty
+    /// and every sender has been dropped.
+    #[cfg(feature = "std")]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.shared
+            .buffer
+            .not_empty
+            .wait_until(
+                || match self.try_recv() {
+                    Ok(v) => Some(Ok(v)),
+                    Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+                    Err(TryRecvError::Empty) => None,
+                },
+                None,
+            )
+            .unwrap()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::Relaxed)
+    }
+
+    /// whether every sender has been dropped, so nothing more will arrive
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.senders.load(Ordering::Acquire) == 0
+    }
+}
+
+impl<T, const N: usize> Clone for Sender<T, N> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T, const N: usize> Clone for Receiver<T, N> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Sender<T, N> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // wake any receivers waiting for data so they notice the disconnection
+            #[cfg(feature = "std")]
+            self.shared.buffer.not_empty.notify();
+            #[cfg(feature = "async")]
+            self.shared.buffer.recv_wakers.notify(usize::MAX);
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Receiver<T, N> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            #[cfg(feature = "std")]
+            self.shared.buffer.not_full.notify();
+            #[cfg(feature = "async")]
+            self.shared.buffer.send_wakers.notify(usize::MAX);
+        }
+    }
+}
+
+/// The error from [`Sender::try_send`], giving back the element that couldn't be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+/// The error from [`Sender::send`] when every receiver has been dropped, giving back the element
+/// that couldn't be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The error from [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+/// The error from [`Receiver::recv`] when the channel is empty and every sender has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("sending on a full channel"),
+            TrySendError::Disconnected(_) => f.write_str("sending on a disconnected channel"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a disconnected channel")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => f.write_str("receiving on a disconnected channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on a disconnected channel")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: fmt::Debug> std::error::Error for TrySendError<T> {}
+#[cfg(feature = "std")]
+impl<T: fmt::Debug> std::error::Error for SendError<T> {}
+#[cfg(feature = "std")]
+impl std::error::Error for TryRecvError {}
+#[cfg(feature = "std")]
+impl std::error::Error for RecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_and_receive() {
+        let (tx, rx) = channel::<u32, 4>();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        for i in 0..4 {
+            assert_eq!(tx.try_send(i), Ok(()));
+        }
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        for i in 0..4 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let (tx, rx) = channel::<u32, 4>();
+        let tx2 = tx.clone();
+        let rx2 = rx.clone();
+        let rx3 = rx.clone();
+        assert_eq!(tx.sender_count(), 2);
+        assert_eq!(rx.receiver_count(), 3);
+        drop(tx2);
+        drop(rx3);
+        assert_eq!(rx2.sender_count(), 1);
+        assert_eq!(tx.receiver_count(), 2);
+    }
+
+    #[test]
+    fn receivers_disconnect_after_draining() {
+        let (tx, rx) = channel::<u32, 4>();
+        let tx2 = tx.clone();
+        assert!(tx.try_send(1).is_ok());
+        drop(tx);
+        assert!(!rx.is_disconnected());
+        assert!(tx2.try_send(2).is_ok());
+        drop(tx2);
+        assert!(rx.is_disconnected());
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn senders_disconnect() {
+        let (tx, rx) = channel::<u32, 4>();
+        drop(rx);
+        assert!(tx.is_disconnected());
+        assert_eq!(tx.try_send(1), Err(TrySendError::Disconnected(1)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn blocked_recv_woken_by_disconnect() {
+        let (tx, rx) = channel::<u32, 4>();
+        let receiver = std::thread::spawn(move || {
+            let mut received = std::vec::Vec::new();
+            while let Ok(v) = rx.recv() {
+                received.push(v);
+            }
+            received
+        });
+        tx.send(1).unwrap();
+        std::thread::sleep(core::time::Duration::from_millis(20));
+        tx.send(2).unwrap();
+        drop(tx);
+        assert_eq!(receiver.join().unwrap(), [1, 2]);
+        let (tx, rx) = channel::<u32, 4>();
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn blocked_send_woken_by_disconnect() {
+        let (tx, rx) = channel::<u32, 2>();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        let sender = std::thread::spawn(move || tx.send(3));
+        std::thread::sleep(core::time::Duration::from_millis(20));
+        drop(rx);
+        assert_eq!(sender.join().unwrap(), Err(SendError(3)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn many_senders_many_receivers() {
+        let (tx, rx) = channel::<u64, 8>();
+        let n = 10_000;
+        let senders: std::vec::Vec<_> = (0..3)
+            .map(|p| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for i in 0..n {
+                        tx.send(i * 3 + p).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        let receivers: std::vec::Vec<_> = (0..3)
+            .map(|_| {
+                let rx = rx.clone();
+                std::thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Ok(v) = rx.recv() {
+                        sum += v;
+                    }
+                    sum
+                })
+            })
+            .collect();
+        drop(rx);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        let sum: u64 = receivers.into_iter().map(|r| r.join().unwrap()).sum();
+        assert_eq!(sum, (3 * n) * (3 * n - 1) / 2);
+    }
+}
@@ -0,0 +1,306 @@
+//! variable-length messages, stored length-prefixed in a byte ring so that each one is contiguous
+//! and can be read where it lies
+
+use core::{fmt, ops::Deref};
+
+use crate::{ByteReader, ByteRing, ByteWriter};
+
+/// the length prefix in front of every record, as a little-endian u32
+const HEADER: usize = 4;
+/// a length prefix saying the rest of the storage, up to the wrap, is padding
+const SKIP: u32 = u32::MAX;
+
+/// A single-producer single-consumer queue of byte messages of any length up to `N - 4`.
+///
+/// Each message is written as a 4-byte length followed by its bytes, and never straddles the end
+/// of the storage: a message that won't fit before the wrap goes at the beginning, and the bytes
+/// it passed over are marked as padding for the reader to skip.
+pub struct FrameRing<const N: usize> {
+    bytes: ByteRing<N>,
+}
+
+impl<const N: usize> FrameRing<N> {
+    /// there has to be room for at least a length prefix, on top of `ByteRing`'s requirements
+    const CHECK_CAPACITY: () = assert!(
+        N > HEADER,
+        "a FrameRing's capacity must be more than the 4 bytes of a length prefix"
+    );
+
+    #[cfg(not(feature = "loom"))]
+    pub const fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        FrameRing {
+            bytes: ByteRing::new(),
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        FrameRing {
+            bytes: ByteRing::new(),
+        }
+    }
+
+    /// splits the ring into its writing and reading halves. any messages left unread when
+    /// they're dropped are still there the next time it's split.
+    pub fn split(&mut self) -> (FrameWriter<'_, N>, FrameReader<'_, N>) {
+        let (bytes, reader) = self.bytes.split();
+        (FrameWriter { bytes }, FrameReader { bytes: reader })
+    }
+}
+
+impl<const N: usize> Default for FrameRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The writing half of a split [`FrameRing`].
+pub struct FrameWriter<'a, const N: usize> {
+    bytes: ByteWriter<'a, N>,
+}
+
+impl<'a, const N: usize> FrameWriter<'a, N> {
+    /// the longest message that could ever be pushed
+    pub const MAX_LEN: usize = N - HEADER;
+
+    /// appends `message` as one record, if there's room for it
+    pub fn try_push_bytes(&mut self, message: &[u8]) -> Result<(), TryPushError> {
+        if message.len() > Self::MAX_LEN || message.len() >= SKIP as usize {
+            return Err(TryPushError::TooLong);
+        }
+        let len = HEADER + message.len();
+        if self.bytes.tail_slice().len() < len {
+            let until_wrap = self.bytes.until_wrap();
+            let region = self.bytes.tail_slice();
+            if region.len() < until_wrap {
+                // the reader hasn't caught up yet
+                return Err(TryPushError::Full);
+            }
+            // the record won't fit before the wrap, so pass over the rest of the storage. that
+            // happens even if there isn't room at the beginning yet, so that once the reader has
+            // skipped the padding there will be.
+            if let Some(header) = region.get_mut(..HEADER) {
+                header.copy_from_slice(&SKIP.to_le_bytes());
+            }
+            self.bytes.commit(until_wrap);
+            if self.bytes.tail_slice().len() < len {
+                return Err(TryPushError::Full);
+            }
+        }
+        let region = self.bytes.tail_slice();
+        region[..HEADER].copy_from_slice(&(message.len() as u32).to_le_bytes());
+        region[HEADER..len].copy_from_slice(message);
+        self.bytes.commit(len);
+        Ok(())
+    }
+}
+
+/// The reading half of a split [`FrameRing`].
+pub struct FrameReader<'a, const N: usize> {
+    bytes: ByteReader<'a, N>,
+}
+
+impl<'a, const N: usize> FrameReader<'a, N> {
+    /// the length of the next message, after skipping any padding in front of it
+    fn next_len(&mut self) -> Option<usize> {
+        loop {
+            let region = self.bytes.read_slice();
+            if region.is_empty() {
+                return None;
+            }
+            // records and padding are committed whole, so what's readable always starts with a
+            // complete one. anything shorter than a header must be padding too short to be marked.
+            let header = match region.get(..HEADER) {
+                Some(header) => u32::from_le_bytes(header.try_into().unwrap()),
+                None => SKIP,
+            };
+            if header != SKIP {
+                return Some(header as usize);
+            }
+            let len = region.len();
+            self.bytes.consume(len);
+        }
+    }
+
+    /// takes the next message, leaving it where it is in the ring until the guard is dropped
+    pub fn try_pop_frame(&mut self) -> Option<FrameGuard<'_, 'a, N>> {
+        let len = self.next_len()?;
+        Some(FrameGuard { reader: self, len })
+    }
+
+    /// copies the next message into the front of `buf` and returns its length. a message too
+    /// long for `buf` is left in the ring.
+    pub fn try_pop_into(&mut self, buf: &mut [u8]) -> Result<usize, TryPopError> {
+        let frame = self.try_pop_frame().ok_or(TryPopError::Empty)?;
+        let len = frame.len();
+        if len > buf.len() {
+            core::mem::forget(frame);
+            return Err(TryPopError::BufferTooSmall(len));
+        }
+        buf[..len].copy_from_slice(&frame);
+        Ok(len)
+    }
+}
+
+/// A message lent out in place by [`FrameReader::try_pop_frame`], which is removed from the ring
+/// when the guard is dropped.
+pub struct FrameGuard<'r, 'a, const N: usize> {
+    reader: &'r mut FrameReader<'a, N>,
+    len: usize,
+}
+
+impl<'r, 'a, const N: usize> Deref for FrameGuard<'r, 'a, N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.reader.bytes.read_slice()[HEADER..HEADER + self.len]
+    }
+}
+
+impl<'r, 'a, const N: usize> Drop for FrameGuard<'r, 'a, N> {
+    fn drop(&mut self) {
+        self.reader.bytes.consume(HEADER + self.len);
+    }
+}
+
+/// The error from [`FrameWriter::try_push_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryPushError {
+    /// there isn't room for the message until the reader takes some out
+    Full,
+    /// the message is longer than [`FrameWriter::MAX_LEN`], so will never fit
+    TooLong,
+}
+
+/// The error from [`FrameReader::try_pop_into`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryPopError {
+    Empty,
+    /// the next message needs a buffer of this length, and has been left in the ring
+    BufferTooSmall(usize),
+}
+
+impl fmt::Display for TryPushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryPushError::Full => f.write_str("pushing onto a full ring"),
+            TryPushError::TooLong => f.write_str("message is longer than the ring can hold"),
+        }
+    }
+}
+
+impl fmt::Display for TryPopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryPopError::Empty => f.write_str("popping from an empty ring"),
+            TryPopError::BufferTooSmall(len) => {
+                write!(f, "buffer is too small for a message of {} bytes", len)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryPushError {}
+#[cfg(feature = "std")]
+impl std::error::Error for TryPopError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut ring = FrameRing::<32>::new();
+        let (mut writer, mut reader) = ring.split();
+        assert!(reader.try_pop_frame().is_none());
+        assert_eq!(writer.try_push_bytes(b"hello"), Ok(()));
+        assert_eq!(writer.try_push_bytes(b""), Ok(()));
+        assert_eq!(writer.try_push_bytes(b"world!"), Ok(()));
+        assert_eq!(writer.try_push_bytes(b"too full"), Err(TryPushError::Full));
+        assert_eq!(writer.try_push_bytes(&[0; 29]), Err(TryPushError::TooLong));
+        let mut buf = [0; 8];
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"hello");
+        assert_eq!(reader.try_pop_into(&mut buf), Ok(0));
+        assert_eq!(
+            reader.try_pop_into(&mut buf[..3]),
+            Err(TryPopError::BufferTooSmall(6))
+        );
+        assert_eq!(reader.try_pop_into(&mut buf), Ok(6));
+        assert_eq!(&buf[..6], b"world!");
+        assert_eq!(reader.try_pop_into(&mut buf), Err(TryPopError::Empty));
+    }
+
+    #[test]
+    fn records_never_straddle_the_wrap() {
+        let mut ring = FrameRing::<16>::new();
+        let (mut writer, mut reader) = ring.split();
+        assert_eq!(writer.try_push_bytes(b"abcdef"), Ok(()));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"abcdef");
+        // 6 bytes left before the wrap, so this goes at the beginning behind a skip marker
+        assert_eq!(writer.try_push_bytes(b"012345"), Ok(()));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"012345");
+        // exactly fills the 6 bytes before the wrap, leaving none to pass over
+        assert_eq!(writer.try_push_bytes(b"xy"), Ok(()));
+        assert_eq!(writer.try_push_bytes(b""), Ok(()));
+        assert_eq!(writer.try_push_bytes(b"1"), Ok(()));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"xy");
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"");
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"1");
+        assert!(reader.try_pop_frame().is_none());
+    }
+
+    #[test]
+    fn padding_too_short_to_mark() {
+        let mut ring = FrameRing::<16>::new();
+        let (mut writer, mut reader) = ring.split();
+        // leaves 2 bytes before the wrap, too few for a skip marker
+        assert_eq!(writer.try_push_bytes(b"0123456789"), Ok(()));
+        assert_eq!(reader.try_pop_into(&mut [0; 16]), Ok(10));
+        assert_eq!(writer.try_push_bytes(b"abc"), Ok(()));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn largest_message_after_the_wrap() {
+        let mut ring = FrameRing::<16>::new();
+        let (mut writer, mut reader) = ring.split();
+        assert_eq!(writer.try_push_bytes(b"abc"), Ok(()));
+        let message = [7; FrameWriter::<16>::MAX_LEN];
+        assert_eq!(writer.try_push_bytes(&message), Err(TryPushError::Full));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), b"abc");
+        // the padding was committed by the failed push, and must be skipped before it fits
+        assert_eq!(writer.try_push_bytes(&message), Err(TryPushError::Full));
+        assert!(reader.try_pop_frame().is_none());
+        assert_eq!(writer.try_push_bytes(&message), Ok(()));
+        assert_eq!(&*reader.try_pop_frame().unwrap(), message);
+    }
+
+    #[test]
+    fn frames_across_threads() {
+        let mut ring = FrameRing::<64>::new();
+        let (mut writer, mut reader) = ring.split();
+        let n = 100_000;
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..n {
+                    let message = [i as u8; 23];
+                    while writer.try_push_bytes(&message[..i % 24]).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            for i in 0..n {
+                loop {
+                    if let Some(frame) = reader.try_pop_frame() {
+                        assert_eq!(&*frame, &[i as u8; 23][..i % 24]);
+                        break;
+                    }
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+}
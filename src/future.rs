@@ -0,0 +1,487 @@
+//! futures that wait for space or data without blocking the thread, for use from async code
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr,
+    sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crate::RingBuffer;
+
+/// An intrusive list of tasks waiting for the buffer to change in some way.
+///
+/// The nodes live inside the futures themselves, which are pinned while linked, so registering
+/// needs no allocation. The list is guarded by a spinlock that is only ever held for a few
+/// pointer updates, never while waking a task.
+///
+/// Each slot published or freed wakes one task, oldest first, rather than all of them. A task
+/// that is woken but dropped before it gets to use the slot passes its wakeup on to the next.
+pub(crate) struct WakerList {
+    locked: AtomicBool,
+    /// the number of linked nodes, so that `notify` can skip the lock when nobody is waiting
+    waiting: AtomicUsize,
+    ends: UnsafeCell<Ends>,
+}
+
+unsafe impl Send for WakerList {}
+unsafe impl Sync for WakerList {}
+
+/// the first and last nodes of a `WakerList`; nodes join at the back and are woken from the front
+struct Ends {
+    head: *mut Node,
+    tail: *mut Node,
+}
+
+struct Node {
+    waker: Option<Waker>,
+    prev: *mut Node,
+    next: *mut Node,
+    linked: bool,
+    /// set when `notify` takes the node off the list, until it registers again
+    woken: bool,
+}
+
+impl Node {
+    const fn new() -> Self {
+        Node {
+            waker: None,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            linked: false,
+            woken: false,
+        }
+    }
+}
+
+impl WakerList {
+    pub(crate) const fn new() -> Self {
+        WakerList {
+            locked: AtomicBool::new(false),
+            waiting: AtomicUsize::new(0),
+            ends: UnsafeCell::new(Ends {
+                head: ptr::null_mut(),
+                tail: ptr::null_mut(),
+            }),
+        }
+    }
+
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Ends) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.ends.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    /// links `node` onto the back of the list if it isn't already there, making sure it will
+    /// wake `waker`
+    ///
+    /// # Safety
+    /// `node` must stay where it is until it has been removed with `unregister`
+    unsafe fn register(&self, node: *mut Node, waker: &Waker) {
+        self.with_lock(|ends| {
+            let node = &mut *node;
+            match &node.waker {
+                Some(w) if w.will_wake(waker) => {}
+                _ => node.waker = Some(waker.clone()),
+            }
+            node.woken = false;
+            if !node.linked {
+                node.prev = ends.tail;
+                node.next = ptr::null_mut();
+                match ends.tail.as_mut() {
+                    Some(tail) => tail.next = node,
+                    None => ends.head = node,
+                }
+                ends.tail = node;
+                node.linked = true;
+                self.waiting.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        // pairs with the fence in `notify`: either the attempt after registering sees the
+        // change, or the notifier sees this node in the list
+        fence(Ordering::SeqCst);
+    }
+
+    /// removes `node` from the list if it's still there. returns whether `notify` took it off
+    /// instead, since it registered last.
+    unsafe fn unregister(&self, node: *mut Node) -> bool {
+        self.with_lock(|ends| {
+            let node = &mut *node;
+            if node.linked {
+                ends.unlink(node);
+                self.waiting.fetch_sub(1, Ordering::Relaxed);
+            }
+            core::mem::replace(&mut node.woken, false)
+        })
+    }
+
+    /// removes `node` from the list for a task that is giving up, handing any wakeup it was
+    /// given on to the next task, as the slot it was woken for is still there for the taking
+    unsafe fn cancel(&self, node: *mut Node) {
+        if self.unregister(node) {
+            self.notify(1);
+        }
+    }
+
+    /// wakes up to `slots` of the tasks that were waiting when this was called, oldest first, to
+    /// be called after publishing or freeing that many slots
+    pub(crate) fn notify(&self, slots: usize) {
+        fence(Ordering::SeqCst);
+        let waiting = self.waiting.load(Ordering::Relaxed);
+        // tasks that register from now on will see the change themselves, and join behind the
+        // ones we have to wake, so taking this many from the front is enough
+        for _ in 0..waiting.min(slots) {
+            let waker = self.with_lock(|ends| unsafe {
+                let node = ends.head.as_mut()?;
+                ends.unlink(node);
+                self.waiting.fetch_sub(1, Ordering::Relaxed);
+                node.woken = true;
+                node.waker.take()
+            });
+            match waker {
+                Some(waker) => waker.wake(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl Ends {
+    unsafe fn unlink(&mut self, node: &mut Node) {
+        match node.prev.as_mut() {
+            Some(prev) => prev.next = node.next,
+            None => self.head = node.next,
+        }
+        match node.next.as_mut() {
+            Some(next) => next.prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        node.linked = false;
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// inserts `v`, waiting until there is space for it. if the future is dropped before it
+    /// completes, `v` is dropped with it and never reaches the buffer; call
+    /// [`SendFuture::cancel`] first to get it back.
+    pub fn send(&self, v: T) -> SendFuture<'_, T, N> {
+        SendFuture {
+            buffer: self,
+            value: Some(v),
+            node: UnsafeCell::new(Node::new()),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// takes the oldest element, waiting until there is one. an element is only taken when the
+    /// future completes, so dropping it early leaves the buffer untouched.
+    pub fn recv(&self) -> RecvFuture<'_, T, N> {
+        RecvFuture {
+            buffer: self,
+            node: UnsafeCell::new(Node::new()),
+            _pinned: PhantomPinned,
+        }
+    }
+}
+
+/// The future returned by [`RingBuffer::send`].
+pub struct SendFuture<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+    value: Option<T>,
+    node: UnsafeCell<Node>,
+    _pinned: PhantomPinned,
+}
+
+unsafe impl<'a, T: Send, const N: usize> Send for SendFuture<'a, T, N> {}
+
+impl<'a, T, const N: usize> SendFuture<'a, T, N> {
+    /// stops waiting and gives back the element, or `None` if it was already inserted. the
+    /// future mustn't be polled again afterwards.
+    pub fn cancel(self: Pin<&mut Self>) -> Option<T> {
+        let this = unsafe { self.get_unchecked_mut() };
+        unsafe { this.buffer.send_wakers.cancel(this.node.get()) };
+        this.value.take()
+    }
+}
+
+impl<'a, T, const N: usize> Future for SendFuture<'a, T, N> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut v = this
+            .value
+            .take()
+            .expect("SendFuture polled after completion");
+        for registered in [false, true] {
+            match this.buffer.try_insert(v) {
+                Ok(()) => {
+                    unsafe { this.buffer.send_wakers.unregister(this.node.get()) };
+                    return Poll::Ready(());
+                }
+                Err(e) => v = e,
+            }
+            if !registered {
+                unsafe {
+                    this.buffer
+                        .send_wakers
+                        .register(this.node.get(), cx.waker())
+                };
+            }
+        }
+        this.value = Some(v);
+        Poll::Pending
+    }
+}
+
+impl<'a, T, const N: usize> Drop for SendFuture<'a, T, N> {
+    fn drop(&mut self) {
+        unsafe { self.buffer.send_wakers.cancel(self.node.get()) };
+    }
+}
+
+/// The future returned by [`RingBuffer::recv`].
+pub struct RecvFuture<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+    node: UnsafeCell<Node>,
+    _pinned: PhantomPinned,
+}
+
+unsafe impl<'a, T: Send, const N: usize> Send for RecvFuture<'a, T, N> {}
+
+impl<'a, T, const N: usize> Future for RecvFuture<'a, T, N> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = unsafe { self.get_unchecked_mut() };
+        for registered in [false, true] {
+            if let Some(v) = this.buffer.try_get() {
+                unsafe { this.buffer.recv_wakers.unregister(this.node.get()) };
+                return Poll::Ready(v);
+            }
+            if !registered {
+                unsafe {
+                    this.buffer
+                        .recv_wakers
+                        .register(this.node.get(), cx.waker())
+                };
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a, T, const N: usize> Drop for RecvFuture<'a, T, N> {
+    fn drop(&mut self) {
+        unsafe { self.buffer.recv_wakers.cancel(self.node.get()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{boxed::Box, sync::Arc, task::Wake, thread::Thread, vec::Vec};
+
+    /// wakes an executor thread by setting its flag and unparking it
+    struct ThreadWaker {
+        woken: AtomicBool,
+        thread: Thread,
+    }
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.woken.store(true, Ordering::Release);
+            self.thread.unpark();
+        }
+    }
+
+    /// a minimal single-threaded executor: polls every unfinished future whenever any of them has
+    /// been woken, and parks the thread otherwise
+    fn run_all<'a>(mut futures: Vec<Pin<Box<dyn Future<Output = ()> + 'a>>>) {
+        let waker = Arc::new(ThreadWaker {
+            woken: AtomicBool::new(true),
+            thread: std::thread::current(),
+        });
+        let task_waker = Waker::from(waker.clone());
+        let mut cx = Context::from_waker(&task_waker);
+        while !futures.is_empty() {
+            if !waker.woken.swap(false, Ordering::Acquire) {
+                std::thread::park();
+                continue;
+            }
+            futures.retain_mut(|f| f.as_mut().poll(&mut cx).is_pending());
+        }
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut output = None;
+        run_all(std::vec![Box::pin(async { output = Some(f.await) })]);
+        output.unwrap()
+    }
+
+    fn noop_waker() -> Waker {
+        struct Noop;
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+        Waker::from(Arc::new(Noop))
+    }
+
+    #[test]
+    fn send_recv_one_thread() {
+        let queue = RingBuffer::<u32, 2>::new();
+        let n = 1000;
+        let mut received = Vec::new();
+        run_all(std::vec![
+            Box::pin(async {
+                for x in 0..n {
+                    queue.send(x).await;
+                }
+            }),
+            Box::pin(async {
+                for _ in 0..n {
+                    received.push(queue.recv().await);
+                }
+            }),
+        ]);
+        assert_eq!(received, (0..n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn send_recv_across_threads() {
+        let queue = RingBuffer::<u64, 4>::new();
+        let n = 10_000;
+        let sum = std::thread::scope(|scope| {
+            for p in 0..2 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    block_on(async {
+                        for i in 0..n {
+                            queue.send(i * 2 + p).await;
+                        }
+                    })
+                });
+            }
+            let consumers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        block_on(async {
+                            let mut sum = 0;
+                            for _ in 0..n {
+                                sum += queue.recv().await;
+                            }
+                            sum
+                        })
+                    })
+                })
+                .collect();
+            consumers
+                .into_iter()
+                .map(|c| c.join().unwrap())
+                .sum::<u64>()
+        });
+        assert_eq!(sum, (2 * n) * (2 * n - 1) / 2);
+    }
+
+    #[test]
+    fn dropped_recv_takes_nothing() {
+        let queue = RingBuffer::<u32, 2>::new();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut recv = Box::pin(queue.recv());
+            assert!(recv.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 0);
+        assert!(queue.try_insert(1).is_ok());
+        assert_eq!(queue.try_get(), Some(1));
+    }
+
+    #[test]
+    fn dropped_send_inserts_nothing() {
+        let queue = RingBuffer::<u32, 2>::new();
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut send = Box::pin(queue.send(3));
+            assert!(send.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(queue.send_wakers.waiting.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.try_get(), Some(1));
+        assert_eq!(queue.try_get(), Some(2));
+        assert_eq!(queue.try_get(), None);
+    }
+
+    #[test]
+    fn one_waiter_woken_per_slot() {
+        let queue = RingBuffer::<u32, 4>::new();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut first = Box::pin(queue.recv());
+        let mut second = Box::pin(queue.recv());
+        let mut third = Box::pin(queue.recv());
+        for recv in [&mut first, &mut second, &mut third] {
+            assert!(recv.as_mut().poll(&mut cx).is_pending());
+        }
+        assert!(queue.try_insert(5).is_ok());
+        // only the oldest waiter is woken for the one element
+        assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 2);
+        assert_eq!(first.as_mut().poll(&mut cx), Poll::Ready(5));
+        assert_eq!(queue.try_insert_slice(&[6, 7]), 2);
+        assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 0);
+        assert_eq!(second.as_mut().poll(&mut cx), Poll::Ready(6));
+        assert_eq!(third.as_mut().poll(&mut cx), Poll::Ready(7));
+    }
+
+    #[test]
+    fn dropped_waiter_hands_its_wakeup_on() {
+        let queue = RingBuffer::<u32, 2>::new();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut first = Box::pin(queue.recv());
+        let mut second = Box::pin(queue.recv());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(queue.try_insert(5).is_ok());
+        assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 1);
+        // woken for the element but dropped without taking it, so the next waiter gets it
+        drop(first);
+        assert_eq!(queue.recv_wakers.waiting.load(Ordering::Relaxed), 0);
+        assert_eq!(second.as_mut().poll(&mut cx), Poll::Ready(5));
+    }
+
+    #[test]
+    fn cancelled_send_gives_the_element_back() {
+        let queue = RingBuffer::<std::string::String, 2>::new();
+        assert!(queue.try_insert("a".into()).is_ok());
+        assert!(queue.try_insert("b".into()).is_ok());
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut send = Box::pin(queue.send("c".into()));
+        assert!(send.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(send.as_mut().cancel().as_deref(), Some("c"));
+        assert_eq!(queue.send_wakers.waiting.load(Ordering::Relaxed), 0);
+        drop(send);
+        assert_eq!(queue.drain().count(), 2);
+        let mut send = Box::pin(queue.send("d".into()));
+        assert!(send.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(send.as_mut().cancel(), None);
+    }
+}
@@ -0,0 +1,342 @@
+//! claiming a slot to construct an element in place or to use it where it is, rather than
+//! moving elements in and out
+
+use core::{
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::{Deref, DerefMut},
+};
+
+use crate::{raw, RingBuffer};
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// claims the next slot for writing, if there is one free, without putting anything in it
+    /// yet. consumers reach the slot in order, so they'll see the buffer as empty from that point
+    /// until the grant is committed or dropped; other producers carry on past it regardless.
+    pub fn try_reserve(&self) -> Option<WriteGrant<'_, T, N>> {
+        match raw::claim_write(&self.end, &self.data, 1) {
+            (place, 1) => Some(WriteGrant {
+                buffer: self,
+                place,
+            }),
+            _ => None,
+        }
+    }
+
+    /// claims the oldest element, if there is one, and lends it out where it sits. the element is
+    /// dropped and its slot handed back to producers when the grant is dropped; meanwhile other
+    /// consumers carry on with the elements after it.
+    pub fn try_peek_grant(&self) -> Option<ReadGrant<'_, T, N>> {
+        loop {
+            match raw::claim_read(&self.start, &self.data, 1) {
+                (place, 1) => {
+                    let slot = raw::slot(&self.data, place);
+                    if unsafe { !slot.is_empty() } {
+                        return Some(ReadGrant {
+                            buffer: self,
+                            place,
+                        });
+                    }
+                    // published empty by an abandoned write, so just pass over it
+                    unsafe { slot.take(place, N) };
+                    self.notify_freed(1);
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// A claimed slot waiting to be filled in, from [`RingBuffer::try_reserve`].
+///
+/// Dropping the grant without committing it publishes the slot empty, so consumers pass over it.
+/// Anything written into it by then is leaked rather than dropped, as the grant can't tell
+/// whether it was fully initialised.
+pub struct WriteGrant<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+    place: usize,
+}
+
+impl<'a, T, const N: usize> WriteGrant<'a, T, N> {
+    /// the slot's storage, to construct the element in
+    pub fn as_uninit_mut(&mut self) -> &mut MaybeUninit<T> {
+        raw::slot(&self.buffer.data, self.place)
+            .value
+            .with_mut(|p| unsafe { &mut *p })
+    }
+
+    /// publishes the element constructed in the slot
+    ///
+    /// # Safety
+    /// the slot must have been fully initialised through `as_uninit_mut`
+    pub unsafe fn commit(self) {
+        let this = ManuallyDrop::new(self);
+        raw::slot(&this.buffer.data, this.place).publish_written(this.place);
+        this.buffer.notify_published(1);
+    }
+
+    /// moves `v` into the slot and publishes it
+    pub fn write(mut self, v: T) {
+        self.as_uninit_mut().write(v);
+        unsafe { self.commit() };
+    }
+}
+
+impl<'a, T, const N: usize> Drop for WriteGrant<'a, T, N> {
+    fn drop(&mut self) {
+        unsafe { raw::slot(&self.buffer.data, self.place).publish(self.place, None) };
+        // consumers stuck behind the slot can now pass over it to anything published after it
+        self.buffer.notify_published(1);
+    }
+}
+
+/// An element claimed from the buffer but left in its slot, from [`RingBuffer::try_peek_grant`].
+///
+/// Sharing the grant between threads shares the element, so it's only `Sync` if the element is:
+///
+/// ```compile_fail
+/// # use core::cell::Cell;
+/// # use ring_buffer::RingBuffer;
+/// fn share<S: Sync>(_: &S) {}
+/// let queue = RingBuffer::<Cell<u32>, 4>::new();
+/// assert!(queue.try_insert(Cell::new(1)).is_ok());
+/// share(&queue.try_peek_grant().unwrap());
+/// ```
+pub struct ReadGrant<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+    place: usize,
+}
+
+// the buffer is `Sync` whenever `T: Send`, but a shared grant hands out `&T`
+unsafe impl<'a, T: Sync, const N: usize> Sync for ReadGrant<'a, T, N> {}
+
+impl<'a, T, const N: usize> ReadGrant<'a, T, N> {
+    /// moves the element out after all, handing its slot back to producers
+    pub fn take(self) -> T {
+        let this = ManuallyDrop::new(self);
+        let v = unsafe { raw::slot(&this.buffer.data, this.place).take(this.place, N) };
+        this.buffer.notify_freed(1);
+        // the grant is only created for slots with a value in them
+        v.unwrap()
+    }
+}
+
+impl<'a, T, const N: usize> Deref for ReadGrant<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        raw::slot(&self.buffer.data, self.place)
+            .value
+            .with(|p| unsafe { (*p).assume_init_ref() })
+    }
+}
+
+impl<'a, T, const N: usize> DerefMut for ReadGrant<'a, T, N> {
+    fn deref_mut(&mut self) -> &mut T {
+        raw::slot(&self.buffer.data, self.place)
+            .value
+            .with_mut(|p| unsafe { (*p).assume_init_mut() })
+    }
+}
+
+impl<'a, T, const N: usize> Drop for ReadGrant<'a, T, N> {
+    fn drop(&mut self) {
+        let slot = raw::slot(&self.buffer.data, self.place);
+        unsafe {
+            slot.value.with_mut(|p| (*p).assume_init_drop());
+            slot.release(self.place, N);
+        }
+        self.buffer.notify_freed(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn construct_in_place() {
+        let queue = RingBuffer::<[u8; 64], 4>::new();
+        let mut grant = queue.try_reserve().unwrap();
+        let frame = grant.as_uninit_mut().as_mut_ptr() as *mut u8;
+        for i in 0..64 {
+            unsafe { frame.add(i).write(i as u8) };
+        }
+        assert_eq!(queue.try_get(), None);
+        unsafe { grant.commit() };
+        let frame = queue.try_get().unwrap();
+        assert!(frame.iter().enumerate().all(|(i, &b)| b == i as u8));
+    }
+
+    #[test]
+    fn write_and_full() {
+        let queue = RingBuffer::<u32, 2>::new();
+        queue.try_reserve().unwrap().write(1);
+        let grant = queue.try_reserve().unwrap();
+        assert!(queue.try_reserve().is_none());
+        assert!(queue.try_insert(3).is_err());
+        grant.write(2);
+        assert_eq!(queue.drain().collect::<std::vec::Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn abandoned_grant_is_skipped() {
+        let queue = RingBuffer::<u32, 4>::new();
+        let grant = queue.try_reserve().unwrap();
+        assert!(queue.try_insert(2).is_ok());
+        // consumers wait at the reserved slot
+        assert_eq!(queue.try_get(), None);
+        drop(grant);
+        assert_eq!(queue.try_get(), Some(2));
+        assert_eq!(queue.try_get(), None);
+        // and the slot is usable again afterwards
+        for i in 0..4 {
+            assert!(queue.try_insert(i).is_ok());
+        }
+        assert_eq!(queue.drain().collect::<std::vec::Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn abandoned_grant_wakes_blocked_consumer() {
+        use std::time::{Duration, Instant};
+
+        let queue = RingBuffer::<u32, 4>::new();
+        let grant = queue.try_reserve().unwrap();
+        std::thread::scope(|scope| {
+            let consumer = scope.spawn(|| {
+                let start = Instant::now();
+                (queue.get_timeout(Duration::from_secs(10)), start.elapsed())
+            });
+            std::thread::sleep(Duration::from_millis(20));
+            // wakes the consumer, which goes back to sleep behind the reserved slot
+            assert!(queue.try_insert(2).is_ok());
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(!consumer.is_finished());
+            drop(grant);
+            let (v, waited) = consumer.join().unwrap();
+            assert_eq!(v, Some(2));
+            assert!(waited < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn forgotten_grant_is_not_dropped() {
+        let value = std::sync::Arc::new(());
+        let queue = RingBuffer::<std::sync::Arc<()>, 4>::new();
+        assert!(queue.try_insert(value.clone()).is_ok());
+        core::mem::forget(queue.try_reserve().unwrap());
+        assert!(queue.try_insert(value.clone()).is_ok());
+        assert_eq!(std::sync::Arc::strong_count(&value), 3);
+        // the forgotten slot was never written, so only the two real elements are dropped
+        drop(queue);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn grants_across_threads() {
+        let queue = RingBuffer::<u64, 8>::new();
+        let n = 10_000;
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..n {
+                        loop {
+                            if let Some(grant) = queue.try_reserve() {
+                                // every other grant is abandoned
+                                if i % 2 == 0 {
+                                    grant.write(i * 2 + p);
+                                }
+                                break;
+                            }
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let mut received = 0;
+            let mut sum = 0;
+            while received < n {
+                match queue.try_get() {
+                    Some(v) => {
+                        sum += v;
+                        received += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+            assert_eq!(sum, (0..n).step_by(2).map(|i| 4 * i + 1).sum::<u64>());
+        });
+    }
+
+    #[test]
+    fn peek_grant_in_place() {
+        let queue = RingBuffer::<std::vec::Vec<u32>, 2>::new();
+        assert!(queue.try_peek_grant().is_none());
+        assert!(queue.try_insert(std::vec![1, 2]).is_ok());
+        assert!(queue.try_insert(std::vec![3]).is_ok());
+        let mut first = queue.try_peek_grant().unwrap();
+        assert_eq!(*first, [1, 2]);
+        first.push(5);
+        // another consumer moves on to the next element meanwhile
+        assert_eq!(queue.try_get(), Some(std::vec![3]));
+        // the granted slot isn't free until the grant is released
+        assert!(queue.try_insert(std::vec![4]).is_err());
+        assert_eq!(first.take(), [1, 2, 5]);
+        assert!(queue.try_insert(std::vec![4]).is_ok());
+        assert!(queue.try_insert(std::vec![6]).is_ok());
+        assert_eq!(queue.drain().count(), 2);
+    }
+
+    #[test]
+    fn peek_grant_drops_in_place() {
+        let value = std::sync::Arc::new(());
+        let queue = RingBuffer::<std::sync::Arc<()>, 2>::new();
+        assert!(queue.try_insert(value.clone()).is_ok());
+        let grant = queue.try_peek_grant().unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&value), 2);
+        drop(grant);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+        assert!(queue.try_get().is_none());
+        assert!(queue.try_insert(value.clone()).is_ok());
+        assert!(queue.try_insert(value.clone()).is_ok());
+    }
+
+    #[test]
+    fn peek_grant_skips_abandoned_writes() {
+        let queue = RingBuffer::<u32, 4>::new();
+        drop(queue.try_reserve());
+        assert!(queue.try_insert(1).is_ok());
+        assert_eq!(queue.try_peek_grant().as_deref(), Some(&1));
+        assert!(queue.try_peek_grant().is_none());
+    }
+
+    #[test]
+    fn peek_grants_across_threads() {
+        let queue = RingBuffer::<u64, 8>::new();
+        let n = 10_000;
+        let total = std::sync::atomic::AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..n {
+                    while queue.try_insert(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    while total.load(std::sync::atomic::Ordering::Relaxed) < n * (n - 1) / 2 {
+                        match queue.try_peek_grant() {
+                            Some(grant) => {
+                                total.fetch_add(*grant, std::sync::atomic::Ordering::Relaxed);
+                            }
+                            None => std::thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(total.into_inner(), n * (n - 1) / 2);
+    }
+}
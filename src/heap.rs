@@ -0,0 +1,194 @@
+//! a ring buffer whose slots live on the heap, for capacities only known at runtime or too big
+//! for the stack
+
+use alloc::boxed::Box;
+
+use crate::{padded::CachePadded, raw, raw::Slot, sync::AtomicUsize};
+
+/// A [`RingBuffer`](crate::RingBuffer) with its slots in a heap allocation sized at runtime.
+pub struct HeapRingBuffer<T> {
+    /// the position of the next element to be taken, plus k * capacity
+    start: CachePadded<AtomicUsize>,
+    /// the position of the next slot to be written, plus k * capacity
+    end: CachePadded<AtomicUsize>,
+    data: Box<[Slot<T>]>,
+}
+
+unsafe impl<T: Send> Send for HeapRingBuffer<T> {}
+unsafe impl<T: Send> Sync for HeapRingBuffer<T> {}
+
+impl<T> HeapRingBuffer<T> {
+    /// creates a buffer with room for at least `capacity` elements. the capacity is rounded up
+    /// to a power of two (and to at least two), for the same reasons as `RingBuffer`'s.
+    ///
+    /// # Panics
+    /// if rounding up `capacity` goes past the largest power of two a usize can hold
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(2).checked_next_power_of_two().expect(
+            "a HeapRingBuffer's capacity must round up to a power of two that fits a usize",
+        );
+        HeapRingBuffer {
+            start: CachePadded::new(AtomicUsize::new(0)),
+            end: CachePadded::new(AtomicUsize::new(0)),
+            data: (0..capacity).map(Slot::new).collect(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    /// how many elements the buffer holds, with the same meaning as `RingBuffer::len`
+    pub fn len(&self) -> usize {
+        raw::len(&self.start, &self.end, self.data.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// whether every slot was claimed by a producer, as for `RingBuffer::is_full`
+    pub fn is_full(&self) -> bool {
+        self.len() == self.data.len()
+    }
+
+    /// how much more room there was, as for `RingBuffer::remaining`
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.len()
+    }
+
+    pub fn try_insert(&self, v: T) -> Result<(), T> {
+        raw::try_insert(&self.end, &self.data, v)
+    }
+
+    pub fn try_get(&self) -> Option<T> {
+        raw::try_get(&self.start, &self.data, &mut 0)
+    }
+
+    /// drops every element currently in the buffer
+    pub fn clear(&self) {
+        while self.try_get().is_some() {}
+    }
+
+    /// returns an iterator that takes elements out of the buffer until it is empty
+    pub fn drain(&self) -> HeapDrain<'_, T> {
+        HeapDrain { buffer: self }
+    }
+}
+
+impl<T> Drop for HeapRingBuffer<T> {
+    fn drop(&mut self) {
+        unsafe { raw::drop_remaining(&self.start, &self.end, &self.data) };
+    }
+}
+
+pub struct HeapDrain<'a, T> {
+    buffer: &'a HeapRingBuffer<T>,
+}
+
+impl<'a, T> Iterator for HeapDrain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.buffer.try_get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Ordering;
+
+    #[test]
+    fn capacity_rounds_up() {
+        assert_eq!(HeapRingBuffer::<u32>::with_capacity(0).capacity(), 2);
+        assert_eq!(HeapRingBuffer::<u32>::with_capacity(5).capacity(), 8);
+        assert_eq!(HeapRingBuffer::<u32>::with_capacity(64).capacity(), 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two that fits a usize")]
+    fn capacity_too_big_to_round_up() {
+        HeapRingBuffer::<u8>::with_capacity(usize::MAX / 2 + 2);
+    }
+
+    #[test]
+    fn single_thread_full() {
+        let queue = HeapRingBuffer::<u32>::with_capacity(4);
+        for round in 0..3 {
+            for i in 0..4 {
+                assert!(queue.try_insert(round * 4 + i).is_ok());
+            }
+            assert_eq!(queue.try_insert(99), Err(99));
+            assert!(queue.is_full());
+            assert_eq!(queue.remaining(), 0);
+            for i in 0..4 {
+                assert_eq!(queue.try_get(), Some(round * 4 + i));
+                assert_eq!(queue.remaining(), i as usize + 1);
+            }
+            assert_eq!(queue.try_get(), None);
+            assert!(!queue.is_full());
+        }
+    }
+
+    #[test]
+    fn large_capacity() {
+        // far too big to build on the stack as an array
+        let queue = HeapRingBuffer::<[u64; 16]>::with_capacity(1 << 16);
+        for i in 0..1 << 16 {
+            assert!(queue.try_insert([i; 16]).is_ok());
+        }
+        assert!(queue.try_insert([0; 16]).is_err());
+        assert_eq!(queue.drain().count(), 1 << 16);
+    }
+
+    #[test]
+    fn two_producer_one_consumer() {
+        let queue = HeapRingBuffer::<u64>::with_capacity(32);
+        let n = 100_000;
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..n / 2 {
+                        while queue.try_insert(i * 2 + p).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let mut x = 0;
+            while x < (n - 1) * n / 2 {
+                if let Some(y) = queue.try_get() {
+                    x += y;
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn drop_remaining() {
+        struct DropCounter<'a>(&'a std::sync::atomic::AtomicUsize);
+
+        impl<'a> Drop for DropCounter<'a> {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let drops = std::sync::atomic::AtomicUsize::new(0);
+        let queue = HeapRingBuffer::with_capacity(4);
+        for _ in 0..6 {
+            assert!(queue.try_insert(DropCounter(&drops)).is_ok());
+            drop(queue.try_get());
+        }
+        for _ in 0..3 {
+            assert!(queue.try_insert(DropCounter(&drops)).is_ok());
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::Relaxed), 9);
+    }
+}
@@ -0,0 +1,269 @@
+//! a ring buffer for sharing with interrupt handlers on single-core targets, where every
+//! operation runs to completion in one critical section instead of retrying a compare-exchange
+
+use core::{marker::PhantomData, mem::MaybeUninit};
+
+use crate::sync::UnsafeCell;
+
+/// A way to run code that nothing else using the same implementation can interrupt, like the
+/// `critical-section` crate's `with`.
+///
+/// On a single-core microcontroller this usually masks interrupts for the duration of the
+/// closure and restores the previous mask afterwards, so that it nests.
+///
+/// # Safety
+/// while `with`'s closure runs, no other call to `with` on the same implementation may be
+/// running its closure, whether on another core, in an interrupt handler or on another thread
+pub unsafe trait CriticalSection {
+    fn with<R>(f: impl FnOnce() -> R) -> R;
+}
+
+/// A bounded multi-producer multi-consumer queue that is safe to use from interrupt handlers.
+///
+/// Every operation takes the critical section `C` once, does a fixed amount of work and leaves,
+/// so an interrupt handler never waits on the thread it preempted. The cost is that interrupts
+/// are held off for as long as it takes to move one element in or out. For a handler talking to
+/// a single thread, the split [`Producer`](crate::Producer) and [`Consumer`](crate::Consumer)
+/// of a `RingBuffer` are wait-free without needing a critical section at all.
+pub struct IsrRingBuffer<T, const N: usize, C> {
+    /// the position of the next element to be taken, plus k * N
+    start: UnsafeCell<usize>,
+    /// the position of the next slot to be written, plus k * N
+    end: UnsafeCell<usize>,
+    data: [UnsafeCell<MaybeUninit<T>>; N],
+    critical_section: PhantomData<C>,
+}
+
+// everything is only touched inside `C::with`
+unsafe impl<T: Send, const N: usize, C> Send for IsrRingBuffer<T, N, C> {}
+unsafe impl<T: Send, const N: usize, C: CriticalSection> Sync for IsrRingBuffer<T, N, C> {}
+
+impl<T, const N: usize, C: CriticalSection> IsrRingBuffer<T, N, C> {
+    /// see `RingBuffer::CHECK_CAPACITY`
+    const CHECK_CAPACITY: () = assert!(
+        N > 1 && N.is_power_of_two(),
+        "an IsrRingBuffer's capacity must be a power of two greater than one"
+    );
+
+    /// usable in a `static`, so that an interrupt handler can reach the buffer
+    #[cfg(not(feature = "loom"))]
+    pub const fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        IsrRingBuffer {
+            start: UnsafeCell::new(0),
+            end: UnsafeCell::new(0),
+            data: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            critical_section: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new() -> Self {
+        let () = Self::CHECK_CAPACITY;
+        IsrRingBuffer {
+            start: UnsafeCell::new(0),
+            end: UnsafeCell::new(0),
+            data: core::array::from_fn(|_| UnsafeCell::new(MaybeUninit::uninit())),
+            critical_section: PhantomData,
+        }
+    }
+
+    /// inserts `v`, or gives it back if the buffer is full. safe to call from an interrupt
+    /// handler: it takes the critical section once and never waits.
+    pub fn try_insert(&self, v: T) -> Result<(), T> {
+        C::with(|| {
+            self.end.with_mut(|end| unsafe {
+                let start = self.start.with(|start| *start);
+                if (*end).wrapping_sub(start) == N {
+                    return Err(v);
+                }
+                self.data[*end & (N - 1)].with_mut(|slot| (*slot).write(v));
+                *end = (*end).wrapping_add(1);
+                Ok(())
+            })
+        })
+    }
+
+    /// takes the oldest element, if there is one. safe to call from an interrupt handler: it
+    /// takes the critical section once and never waits.
+    pub fn try_get(&self) -> Option<T> {
+        C::with(|| {
+            self.start.with_mut(|start| unsafe {
+                if *start == self.end.with(|end| *end) {
+                    return None;
+                }
+                let v = self.data[*start & (N - 1)].with(|slot| (*slot).assume_init_read());
+                *start = (*start).wrapping_add(1);
+                Some(v)
+            })
+        })
+    }
+
+    /// how many elements there are. safe to call from an interrupt handler, though by the time
+    /// it returns, another handler may have changed it.
+    pub fn len(&self) -> usize {
+        C::with(|| {
+            let start = self.start.with(|start| unsafe { *start });
+            self.end.with(|end| unsafe { *end }).wrapping_sub(start)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, const N: usize, C: CriticalSection> Default for IsrRingBuffer<T, N, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize, C> Drop for IsrRingBuffer<T, N, C> {
+    fn drop(&mut self) {
+        let start = self.start.with(|start| unsafe { *start });
+        let end = self.end.with(|end| unsafe { *end });
+        for i in 0..end.wrapping_sub(start) {
+            let place = start.wrapping_add(i);
+            self.data[place & (N - 1)].with_mut(|slot| unsafe { (*slot).assume_init_drop() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, sync::Mutex, vec::Vec};
+
+    /// stands in for a single core: whoever holds it is running, and the critical section and
+    /// interrupt handlers both take it, so a handler can only run between critical sections
+    static CORE: Mutex<()> = Mutex::new(());
+
+    std::thread_local! {
+        /// how deep the current thread is in critical sections, so that they nest
+        static DEPTH: Cell<usize> = const { Cell::new(0) };
+    }
+
+    struct MaskInterrupts;
+
+    unsafe impl CriticalSection for MaskInterrupts {
+        fn with<R>(f: impl FnOnce() -> R) -> R {
+            let _core = (DEPTH.get() == 0).then(|| CORE.lock().unwrap_or_else(|e| e.into_inner()));
+            DEPTH.set(DEPTH.get() + 1);
+            let r = f();
+            DEPTH.set(DEPTH.get() - 1);
+            r
+        }
+    }
+
+    /// runs `handler` as an interrupt would: at some point outside any critical section of the
+    /// interrupted code, and to completion before that code carries on
+    fn interrupt<R>(handler: impl FnOnce() -> R) -> R {
+        MaskInterrupts::with(handler)
+    }
+
+    #[test]
+    fn single_context() {
+        let queue = IsrRingBuffer::<u32, 4, MaskInterrupts>::new();
+        for round in 0..3 {
+            for i in 0..4 {
+                assert!(queue.try_insert(round * 4 + i).is_ok());
+            }
+            assert_eq!(queue.try_insert(99), Err(99));
+            assert_eq!(queue.len(), 4);
+            for i in 0..4 {
+                assert_eq!(queue.try_get(), Some(round * 4 + i));
+            }
+            assert_eq!(queue.try_get(), None);
+            assert!(queue.is_empty());
+        }
+    }
+
+    #[test]
+    fn critical_sections_nest() {
+        let queue = IsrRingBuffer::<u32, 4, MaskInterrupts>::new();
+        MaskInterrupts::with(|| {
+            assert!(queue.try_insert(1).is_ok());
+            assert_eq!(queue.try_get(), Some(1));
+        });
+    }
+
+    #[test]
+    fn handler_feeds_thread() {
+        let queue = IsrRingBuffer::<u32, 8, MaskInterrupts>::new();
+        let n = 10_000;
+        std::thread::scope(|scope| {
+            // a timer interrupt producing samples, firing whenever the thread isn't in a
+            // critical section
+            scope.spawn(|| {
+                let mut next = 0;
+                while next < n {
+                    interrupt(|| {
+                        // a handler mustn't wait, so one that finds the buffer full drops the
+                        // sample and tries again next time it fires
+                        if queue.try_insert(next).is_ok() {
+                            next += 1;
+                        }
+                    });
+                    std::thread::yield_now();
+                }
+            });
+            let mut received = Vec::new();
+            while received.len() < n as usize {
+                match queue.try_get() {
+                    Some(v) => received.push(v),
+                    None => std::thread::yield_now(),
+                }
+            }
+            assert!(received.iter().copied().eq(0..n));
+        });
+    }
+
+    #[test]
+    fn handlers_and_threads_share() {
+        let queue = IsrRingBuffer::<u64, 4, MaskInterrupts>::new();
+        let n = 2_000;
+        let total = Mutex::new(0);
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..n {
+                        while queue.try_insert(i * 2 + p).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    let mut taken = 0;
+                    while taken < n {
+                        match interrupt(|| queue.try_get()) {
+                            Some(v) => {
+                                *total.lock().unwrap() += v;
+                                taken += 1;
+                            }
+                            None => std::thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+        let n = 2 * n;
+        assert_eq!(total.into_inner().unwrap(), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn drop_remaining() {
+        let value = std::sync::Arc::new(());
+        let queue = IsrRingBuffer::<_, 4, MaskInterrupts>::new();
+        for _ in 0..3 {
+            assert!(queue.try_insert(value.clone()).is_ok());
+        }
+        drop(queue.try_get());
+        assert_eq!(std::sync::Arc::strong_count(&value), 3);
+        drop(queue);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,155 @@
+//! a lossy insert that makes room by evicting the oldest element, for when the newest data
+//! matters more than the oldest
+
+use crate::{
+    raw,
+    sync::{spin_loop, Ordering},
+    RingBuffer,
+};
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// inserts `v`, evicting the oldest element if the buffer is full, and returns the evicted
+    /// element.
+    ///
+    /// the oldest element is claimed the same way a consumer would claim it, so each element is
+    /// still either evicted or taken, never both. if a consumer is part way through taking the
+    /// element in the slot needed, or a producer part way through writing it, this waits for them
+    /// to finish. `v` goes straight into the slot it evicted from, so at most one element is
+    /// evicted per call.
+    ///
+    /// the wait has no bound, so this never returns if the slot is never released: if the
+    /// calling thread itself holds a `ReadGrant` for the oldest element, say, or the slot was
+    /// claimed by a `WriteGrant` that was `mem::forget`-ed.
+    pub fn force_insert(&self, v: T) -> Option<T> {
+        let mut v = v;
+        loop {
+            match raw::try_insert(&self.end, &self.data, v) {
+                Ok(()) => {
+                    self.notify_published(1);
+                    return None;
+                }
+                Err(back) => v = back,
+            }
+            let place = self.end.load(Ordering::Relaxed);
+            let oldest = place.wrapping_sub(N);
+            let slot = raw::slot(&self.data, place);
+            // only the element from the previous lap in the very slot needed is evicted, and only
+            // if no consumer has claimed it yet
+            if slot.stamp.load(Ordering::Acquire) == oldest.wrapping_add(1)
+                && self
+                    .start
+                    .compare_exchange(
+                        oldest,
+                        oldest.wrapping_add(1),
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                // until the slot is published again, producers see it as still full, so `end`
+                // can't move on from `place` and nobody else can claim it
+                let old = unsafe { slot.take_value() };
+                self.end.store(place.wrapping_add(1), Ordering::Relaxed);
+                unsafe { slot.publish(place, Some(v)) };
+                self.notify_published(1);
+                if old.is_some() {
+                    self.overwritten.fetch_add(1, Ordering::Relaxed);
+                }
+                return old;
+            }
+            spin_loop();
+        }
+    }
+
+    /// how many elements `force_insert` has evicted
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_oldest() {
+        let queue = RingBuffer::<u32, 4>::new();
+        for i in 0..4 {
+            assert_eq!(queue.force_insert(i), None);
+        }
+        assert_eq!(queue.force_insert(4), Some(0));
+        assert_eq!(queue.force_insert(5), Some(1));
+        assert_eq!(queue.overwritten(), 2);
+        assert_eq!(queue.try_get(), Some(2));
+        assert_eq!(queue.force_insert(6), None);
+        assert_eq!(queue.drain().collect::<std::vec::Vec<_>>(), [3, 4, 5, 6]);
+        assert_eq!(queue.overwritten(), 2);
+    }
+
+    #[test]
+    fn skips_abandoned_writes() {
+        let queue = RingBuffer::<u32, 2>::new();
+        drop(queue.try_reserve());
+        assert!(queue.try_insert(1).is_ok());
+        // the empty slot is reclaimed without counting as an eviction
+        assert_eq!(queue.force_insert(2), None);
+        assert_eq!(queue.overwritten(), 0);
+        assert_eq!(queue.force_insert(3), Some(1));
+        assert_eq!(queue.overwritten(), 1);
+    }
+
+    #[test]
+    fn waits_for_a_consumer_holding_the_slot() {
+        let queue = RingBuffer::<u32, 2>::new();
+        assert!(queue.try_insert(0).is_ok());
+        assert!(queue.try_insert(1).is_ok());
+        let grant = queue.try_peek_grant().unwrap();
+        std::thread::scope(|scope| {
+            let producer = scope.spawn(|| queue.force_insert(2));
+            std::thread::sleep(core::time::Duration::from_millis(20));
+            assert!(!producer.is_finished());
+            assert_eq!(*grant, 0);
+            drop(grant);
+            // the slot freed by the consumer is used rather than evicting anything
+            assert_eq!(producer.join().unwrap(), None);
+        });
+        assert_eq!(queue.drain().collect::<std::vec::Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn every_element_evicted_or_taken_once() {
+        let queue = RingBuffer::<u64, 4>::new();
+        let n = 20_000;
+        let evicted = std::sync::atomic::AtomicU64::new(0);
+        let evictions = std::sync::atomic::AtomicUsize::new(0);
+        let taken = std::sync::atomic::AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let (queue, evicted, evictions) = (&queue, &evicted, &evictions);
+                scope.spawn(move || {
+                    for i in 0..n / 2 {
+                        if let Some(v) = queue.force_insert(i * 2 + p) {
+                            evicted.fetch_add(v, std::sync::atomic::Ordering::Relaxed);
+                            evictions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+            scope.spawn(|| {
+                for _ in 0..n / 4 {
+                    if let Some(v) = queue.try_get() {
+                        taken.fetch_add(v, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    std::thread::yield_now();
+                }
+            });
+        });
+        // every eviction counted was handed back to a caller
+        assert_eq!(evictions.into_inner(), queue.overwritten());
+        let left: u64 = queue.drain().sum();
+        assert_eq!(
+            evicted.into_inner() + taken.into_inner() + left,
+            n * (n - 1) / 2
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! padding that keeps a value on a cache line of its own, so that threads hammering one counter
+//! don't keep stealing the line from threads using the counter next to it
+
+use core::ops::Deref;
+
+/// `T`, aligned to (and so padded out to) the size of a cache line, or of the pair of lines
+/// that the prefetcher pulls in together.
+///
+/// the sizes follow what x86_64 and the big aarch64 and powerpc64 cores prefetch (two 64-byte
+/// lines), s390x's 256-byte lines, and the 32-byte lines of small 32-bit cores. building with
+/// `--cfg ring_buffer_unpadded` leaves the padding out, to measure what it's worth.
+#[cfg_attr(
+    all(
+        not(ring_buffer_unpadded),
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "powerpc64",
+        ),
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    all(not(ring_buffer_unpadded), target_arch = "s390x"),
+    repr(align(256))
+)]
+#[cfg_attr(
+    all(
+        not(ring_buffer_unpadded),
+        any(
+            target_arch = "arm",
+            target_arch = "mips",
+            target_arch = "riscv32",
+            target_arch = "hexagon",
+        ),
+    ),
+    repr(align(32))
+)]
+#[cfg_attr(
+    all(
+        not(ring_buffer_unpadded),
+        not(any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "powerpc64",
+            target_arch = "s390x",
+            target_arch = "arm",
+            target_arch = "mips",
+            target_arch = "riscv32",
+            target_arch = "hexagon",
+        )),
+    ),
+    repr(align(64))
+)]
+#[derive(Debug, Default)]
+#[repr(C)]
+pub(crate) struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(all(test, not(ring_buffer_unpadded)))]
+mod tests {
+    use super::*;
+    use crate::{sync::AtomicUsize, RingBuffer};
+
+    #[test]
+    fn counters_on_their_own_lines() {
+        let align = core::mem::align_of::<CachePadded<AtomicUsize>>();
+        assert!(align >= 32);
+        assert_eq!(core::mem::size_of::<CachePadded<AtomicUsize>>(), align);
+        let queue = RingBuffer::<u8, 4>::new();
+        let start = &*queue.start as *const AtomicUsize as usize;
+        let end = &*queue.end as *const AtomicUsize as usize;
+        assert!(start.abs_diff(end) >= align);
+    }
+}
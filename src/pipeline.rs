@@ -0,0 +1,382 @@
+//! a disruptor-style pipeline: one slot array that a publisher fills and a chain (or graph) of
+//! stages then works through in place, each gated on the sequences of the stages it depends on
+
+use crate::{
+    padded::CachePadded,
+    retained::Retained,
+    sync::{AtomicUsize, Ordering},
+};
+
+/// A bounded single-publisher queue whose elements are processed in place by `S` stages.
+///
+/// Each stage has a sequence saying how far through the elements it's got, and only goes as far
+/// as the slowest of the stages it depends on, or as far as the publisher has got if it depends
+/// on none. The publisher in turn only goes as far as `N` elements past the slowest stage. So an
+/// element moves from stage to stage without ever being copied, and a chain like decode, enrich,
+/// persist needs one slot array rather than one buffer per hop.
+///
+/// A stage that is ordered against every other stage, either depending on it or depended on by
+/// it, is the only one looking at an element while it has it, and may change it. Stages that
+/// run side by side may only look.
+pub struct Pipeline<T, const N: usize, const S: usize> {
+    /// the position of the next element to be published, plus k * N
+    published: CachePadded<AtomicUsize>,
+    /// stages work on elements where they are, so each stays until the publisher comes round to
+    /// its slot again
+    slots: [Retained<T>; N],
+    /// the position of the next element each stage will process
+    sequences: [CachePadded<AtomicUsize>; S],
+    /// bit j of `upstream[i]` is set if stage i waits for stage j. a stage that waits for no
+    /// stage waits for the publisher.
+    upstream: [usize; S],
+    /// bit i is set if stage i is ordered against every other stage
+    exclusive: usize,
+}
+
+unsafe impl<T: Send, const N: usize, const S: usize> Send for Pipeline<T, N, S> {}
+// stages running side by side look at the same element at once
+unsafe impl<T: Send + Sync, const N: usize, const S: usize> Sync for Pipeline<T, N, S> {}
+
+impl<T, const N: usize, const S: usize> Pipeline<T, N, S> {
+    /// see `RingBuffer::CHECK_CAPACITY`. the stages are tracked with one bit each in a usize.
+    const CHECK_CAPACITY: () = assert!(
+        N > 1 && N.is_power_of_two() && S <= usize::BITS as usize,
+        "a Pipeline's capacity must be a power of two greater than one, with at most \
+         usize::BITS stages"
+    );
+
+    /// a pipeline where stage i waits for the stages listed in `dependencies[i]`, or for the
+    /// publisher if the list is empty.
+    ///
+    /// # Panics
+    /// if a stage depends on itself or on a stage after it, which also rules out cycles
+    #[cfg(not(feature = "loom"))]
+    pub const fn new(dependencies: [&[usize]; S]) -> Self {
+        let () = Self::CHECK_CAPACITY;
+        let (upstream, exclusive) = Self::gating(dependencies);
+        let mut slots = [const { core::mem::MaybeUninit::<Retained<T>>::uninit() }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i] = core::mem::MaybeUninit::new(Retained::new());
+            i += 1;
+        }
+        Pipeline {
+            published: CachePadded::new(AtomicUsize::new(0)),
+            slots: unsafe { (&slots as *const _ as *const [Retained<T>; N]).read() },
+            sequences: [const { CachePadded::new(AtomicUsize::new(0)) }; S],
+            upstream,
+            exclusive,
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub fn new(dependencies: [&[usize]; S]) -> Self {
+        let () = Self::CHECK_CAPACITY;
+        let (upstream, exclusive) = Self::gating(dependencies);
+        Pipeline {
+            published: CachePadded::new(AtomicUsize::new(0)),
+            slots: core::array::from_fn(|_| Retained::new()),
+            sequences: core::array::from_fn(|_| CachePadded::new(AtomicUsize::new(0))),
+            upstream,
+            exclusive,
+        }
+    }
+
+    /// turns the dependency lists into the `upstream` and `exclusive` masks
+    const fn gating(dependencies: [&[usize]; S]) -> ([usize; S], usize) {
+        let mut upstream = [0; S];
+        // every stage each stage waits for, directly or not
+        let mut ancestors = [0; S];
+        let mut i = 0;
+        while i < S {
+            let mut d = 0;
+            while d < dependencies[i].len() {
+                let j = dependencies[i][d];
+                assert!(
+                    j < i,
+                    "a pipeline stage may only depend on stages before it"
+                );
+                upstream[i] |= 1 << j;
+                ancestors[i] |= 1 << j | ancestors[j];
+                d += 1;
+            }
+            i += 1;
+        }
+        let mut exclusive = 0;
+        let mut i = 0;
+        while i < S {
+            let mut ordered = true;
+            let mut j = 0;
+            while j < S {
+                if j != i && ancestors[i] & 1 << j == 0 && ancestors[j] & 1 << i == 0 {
+                    ordered = false;
+                }
+                j += 1;
+            }
+            if ordered {
+                exclusive |= 1 << i;
+            }
+            i += 1;
+        }
+        (upstream, exclusive)
+    }
+
+    fn slot(&self, place: usize) -> &Retained<T> {
+        &self.slots[place & (N - 1)]
+    }
+
+    /// the sequence of the stage furthest behind the publisher
+    fn slowest(&self) -> usize {
+        let published = self.published.load(Ordering::Relaxed);
+        // pairs with the release in `Stage::finish`, so that every stage is done with the old
+        // elements before they're dropped
+        let behind = self
+            .sequences
+            .iter()
+            .map(|sequence| published.wrapping_sub(sequence.load(Ordering::Acquire)))
+            .max()
+            .unwrap_or(0);
+        published.wrapping_sub(behind)
+    }
+
+    /// splits the pipeline into its publisher and one handle per stage, in the order the
+    /// dependencies were given. the sequences live in the pipeline, so splitting it again later
+    /// carries on where the last handles left off.
+    pub fn split(&mut self) -> (Publisher<'_, T, N, S>, [Stage<'_, T, N, S>; S]) {
+        let pipeline = &*self;
+        (
+            Publisher {
+                pipeline,
+                slowest: pipeline.slowest(),
+            },
+            core::array::from_fn(|index| Stage { pipeline, index }),
+        )
+    }
+}
+
+/// The handle that feeds elements into a [`Pipeline`].
+pub struct Publisher<'a, T, const N: usize, const S: usize> {
+    pipeline: &'a Pipeline<T, N, S>,
+    /// the last sequence seen for the slowest stage, which stages can only have moved on from.
+    /// going by it saves pulling every stage's cache line over until it says the ring is full.
+    slowest: usize,
+}
+
+impl<'a, T, const N: usize, const S: usize> Publisher<'a, T, N, S> {
+    /// publishes `v` to the stages that wait for the publisher. fails if the slowest stage is
+    /// still `N` elements behind, since the slot it'd go in is still in use.
+    pub fn try_publish(&mut self, v: T) -> Result<(), T> {
+        let pipeline = self.pipeline;
+        let place = pipeline.published.load(Ordering::Relaxed);
+        if place.wrapping_sub(self.slowest) >= N {
+            self.slowest = pipeline.slowest();
+            if place.wrapping_sub(self.slowest) >= N {
+                return Err(v);
+            }
+        }
+        unsafe { pipeline.slot(place).replace(v) };
+        pipeline
+            .published
+            .store(place.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+/// One stage of a [`Pipeline`], from [`Pipeline::split`].
+pub struct Stage<'a, T, const N: usize, const S: usize> {
+    pipeline: &'a Pipeline<T, N, S>,
+    index: usize,
+}
+
+impl<'a, T, const N: usize, const S: usize> Stage<'a, T, N, S> {
+    /// whether this stage is ordered against every other one, and so may change elements
+    pub fn is_exclusive(&self) -> bool {
+        self.pipeline.exclusive & 1 << self.index != 0
+    }
+
+    /// the place this stage is up to and how many elements are ready for it
+    fn available(&self) -> (usize, usize) {
+        let pipeline = self.pipeline;
+        let cursor = pipeline.sequences[self.index].load(Ordering::Relaxed);
+        let mut upstream = pipeline.upstream[self.index];
+        if upstream == 0 {
+            let published = pipeline.published.load(Ordering::Acquire);
+            return (cursor, published.wrapping_sub(cursor));
+        }
+        let mut count = usize::MAX;
+        while upstream != 0 {
+            let index = upstream.trailing_zeros() as usize;
+            upstream &= upstream - 1;
+            let sequence = pipeline.sequences[index].load(Ordering::Acquire);
+            count = count.min(sequence.wrapping_sub(cursor));
+        }
+        (cursor, count)
+    }
+
+    /// hands the elements up to `place` on to the stages downstream, and back to the publisher
+    fn finish(&self, place: usize) {
+        self.pipeline.sequences[self.index].store(place, Ordering::Release);
+    }
+
+    /// calls `f` on every element that's ready for this stage, in order, then passes them all
+    /// on at once. returns how many there were.
+    ///
+    /// # Panics
+    /// if the stage isn't exclusive, since a stage running beside it could be looking at the
+    /// same elements
+    pub fn process_available(&mut self, mut f: impl FnMut(&mut T)) -> usize {
+        assert!(
+            self.is_exclusive(),
+            "only a stage ordered against every other stage may change elements"
+        );
+        let (cursor, count) = self.available();
+        for i in 0..count {
+            let slot = self.pipeline.slot(cursor.wrapping_add(i));
+            unsafe { slot.with_mut(&mut f) };
+        }
+        self.finish(cursor.wrapping_add(count));
+        count
+    }
+
+    /// calls `f` on every element that's ready for this stage, in order, without changing them,
+    /// then passes them all on at once. returns how many there were.
+    pub fn inspect_available(&mut self, mut f: impl FnMut(&T)) -> usize {
+        let (cursor, count) = self.available();
+        for i in 0..count {
+            let slot = self.pipeline.slot(cursor.wrapping_add(i));
+            unsafe { slot.with(&mut f) };
+        }
+        self.finish(cursor.wrapping_add(count));
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn stages_run_in_order() {
+        let mut pipeline = Pipeline::<u32, 4, 3>::new([&[], &[0], &[1]]);
+        let (mut publisher, [mut decode, mut enrich, mut persist]) = pipeline.split();
+        assert!(publisher.try_publish(1).is_ok());
+        assert!(publisher.try_publish(2).is_ok());
+        // nothing has been decoded yet
+        assert_eq!(enrich.process_available(|_| unreachable!()), 0);
+        assert_eq!(decode.process_available(|v| *v *= 10), 2);
+        assert!(publisher.try_publish(3).is_ok());
+        assert_eq!(enrich.process_available(|v| *v += 1), 2);
+        let mut persisted = Vec::new();
+        assert_eq!(persist.inspect_available(|&v| persisted.push(v)), 2);
+        assert_eq!(decode.process_available(|v| *v *= 10), 1);
+        assert_eq!(enrich.process_available(|v| *v += 1), 1);
+        assert_eq!(persist.inspect_available(|&v| persisted.push(v)), 1);
+        assert_eq!(persisted, [11, 21, 31]);
+    }
+
+    #[test]
+    fn publisher_waits_for_the_slowest_stage() {
+        let mut pipeline = Pipeline::<u32, 4, 2>::new([&[], &[0]]);
+        let (mut publisher, [mut first, mut last]) = pipeline.split();
+        for i in 0..4 {
+            assert!(publisher.try_publish(i).is_ok());
+        }
+        assert_eq!(publisher.try_publish(4), Err(4));
+        assert_eq!(first.inspect_available(|_| {}), 4);
+        assert_eq!(publisher.try_publish(4), Err(4));
+        let mut seen = Vec::new();
+        assert_eq!(last.inspect_available(|&v| seen.push(v)), 4);
+        assert!(publisher.try_publish(4).is_ok());
+        assert_eq!(first.inspect_available(|_| {}), 1);
+        assert_eq!(last.inspect_available(|&v| seen.push(v)), 1);
+        assert_eq!(seen, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn side_by_side_stages_only_look() {
+        let mut pipeline = Pipeline::<u32, 4, 3>::new([&[], &[], &[0, 1]]);
+        let (mut publisher, [mut journal, mut replicate, mut apply]) = pipeline.split();
+        assert!(!journal.is_exclusive());
+        assert!(!replicate.is_exclusive());
+        assert!(apply.is_exclusive());
+        assert!(publisher.try_publish(1).is_ok());
+        assert_eq!(journal.inspect_available(|_| {}), 1);
+        // the join waits for both of the stages before it
+        assert_eq!(apply.process_available(|_| unreachable!()), 0);
+        assert_eq!(replicate.inspect_available(|_| {}), 1);
+        assert_eq!(apply.process_available(|v| *v += 1), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ordered against every other stage")]
+    fn side_by_side_stage_cannot_change_elements() {
+        let mut pipeline = Pipeline::<u32, 4, 2>::new([&[], &[]]);
+        let (_, [mut a, _]) = pipeline.split();
+        a.process_available(|_| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "only depend on stages before it")]
+    fn dependencies_must_point_backwards() {
+        Pipeline::<u32, 4, 2>::new([&[1], &[]]);
+    }
+
+    #[test]
+    fn overwritten_elements_are_dropped() {
+        let value = std::sync::Arc::new(());
+        let mut pipeline = Pipeline::<std::sync::Arc<()>, 2, 1>::new([&[]]);
+        let (mut publisher, [mut stage]) = pipeline.split();
+        for _ in 0..3 {
+            assert!(publisher.try_publish(value.clone()).is_ok());
+            assert_eq!(stage.inspect_available(|_| {}), 1);
+        }
+        assert_eq!(std::sync::Arc::strong_count(&value), 3);
+        drop(pipeline);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn pipeline_across_threads() {
+        let mut pipeline = Pipeline::<u64, 16, 3>::new([&[], &[0], &[1]]);
+        let n = 100_000;
+        let (mut publisher, [mut decode, mut enrich, mut persist]) = pipeline.split();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..n {
+                    while publisher.try_publish(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            scope.spawn(move || {
+                let mut done = 0;
+                while done < n {
+                    match decode.process_available(|v| *v *= 2) {
+                        0 => std::thread::yield_now(),
+                        count => done += count as u64,
+                    }
+                }
+            });
+            scope.spawn(move || {
+                let mut done = 0;
+                while done < n {
+                    match enrich.process_available(|v| *v += 1) {
+                        0 => std::thread::yield_now(),
+                        count => done += count as u64,
+                    }
+                }
+            });
+            let mut next = 0;
+            while next < n {
+                let count = persist.inspect_available(|&v| {
+                    assert_eq!(v, next * 2 + 1);
+                    next += 1;
+                });
+                if count == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,258 @@
+//! the slot-stamp algorithm itself, over a slice of slots so that every kind of buffer can share
+//! it whatever its storage. the slice's length must be a power of two greater than one.
+
+use core::mem::MaybeUninit;
+
+#[cfg(all(feature = "backoff", target_has_atomic = "ptr"))]
+use crate::backoff::Backoff;
+use crate::sync::{AtomicUsize, Ordering, UnsafeCell};
+
+/// `repr(C)` so that a slot has the same layout in every process sharing a `ShmRing`
+#[repr(C)]
+pub(crate) struct Slot<T> {
+    /// `place` while the slot is free to be written for `place`, `place + 1` once the element for
+    /// `place` has been published into it
+    pub(crate) stamp: AtomicUsize,
+    pub(crate) value: UnsafeCell<MaybeUninit<T>>,
+    /// set when a producer gave up on the place it claimed and published the slot without a
+    /// value, so that consumers just release it and move on
+    pub(crate) empty: UnsafeCell<bool>,
+}
+
+impl<T> Slot<T> {
+    #[cfg(not(feature = "loom"))]
+    pub(crate) const fn new(place: usize) -> Self {
+        Slot {
+            stamp: AtomicUsize::new(place),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            empty: UnsafeCell::new(false),
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub(crate) fn new(place: usize) -> Self {
+        Slot {
+            stamp: AtomicUsize::new(place),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            empty: UnsafeCell::new(false),
+        }
+    }
+
+    /// writes `v` into the slot, or marks it empty, and hands it over to consumers
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for writing at `place`
+    pub(crate) unsafe fn publish(&self, place: usize, v: Option<T>) {
+        match v {
+            Some(v) => self.value.with_mut(|p| p.write(MaybeUninit::new(v))),
+            None => self.empty.with_mut(|p| *p = true),
+        }
+        self.publish_written(place);
+    }
+
+    /// hands the slot over to consumers after its value has been written in place
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for writing at `place` and initialised its value
+    pub(crate) unsafe fn publish_written(&self, place: usize) {
+        self.stamp.store(place.wrapping_add(1), Ordering::Release);
+    }
+
+    /// whether the slot was published without a value
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for reading
+    pub(crate) unsafe fn is_empty(&self) -> bool {
+        self.empty.with(|p| *p)
+    }
+
+    /// takes the value out of the slot, if it has one, and hands it back to producers for the
+    /// place `capacity` further on
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for reading at `place`
+    pub(crate) unsafe fn take(&self, place: usize, capacity: usize) -> Option<T> {
+        let v = self.take_value();
+        self.release(place, capacity);
+        v
+    }
+
+    /// moves the value out of the slot, if it has one, without handing the slot back
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for reading
+    pub(crate) unsafe fn take_value(&self) -> Option<T> {
+        if self.empty.with_mut(|p| core::mem::replace(&mut *p, false)) {
+            None
+        } else {
+            Some(self.value.with(|p| p.read().assume_init()))
+        }
+    }
+
+    /// hands the slot back to producers for the place `capacity` further on, once its value has
+    /// been moved out or dropped in place
+    ///
+    /// # Safety
+    /// the caller must have claimed the slot for reading at `place` and be done with its value
+    pub(crate) unsafe fn release(&self, place: usize, capacity: usize) {
+        self.stamp
+            .store(place.wrapping_add(capacity), Ordering::Release);
+    }
+}
+
+/// the slot that `place` maps to
+pub(crate) fn slot<T>(slots: &[Slot<T>], place: usize) -> &Slot<T> {
+    &slots[place & (slots.len() - 1)]
+}
+
+/// claims up to `max` consecutive slots from `end` with a single compare-exchange, returning the
+/// first place claimed and how many were claimed
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) fn claim_write<T>(end: &AtomicUsize, slots: &[Slot<T>], max: usize) -> (usize, usize) {
+    let mut place = end.load(Ordering::Relaxed);
+    #[cfg(feature = "backoff")]
+    let mut backoff = Backoff::new();
+    loop {
+        let mut count = 0;
+        // a slot that is free for its place now stays that way until someone claims it through
+        // `end`, so checking them before the compare-exchange is enough
+        while count < max.min(slots.len())
+            && slot(slots, place.wrapping_add(count))
+                .stamp
+                .load(Ordering::Acquire)
+                == place.wrapping_add(count)
+        {
+            count += 1;
+        }
+        if count == 0 {
+            let stamp = slot(slots, place).stamp.load(Ordering::Relaxed);
+            if max == 0 || (stamp.wrapping_sub(place) as isize) < 0 {
+                // the element from the previous lap hasn't been taken yet
+                return (place, 0);
+            }
+            // another producer claimed this place since we loaded `end`
+            #[cfg(feature = "backoff")]
+            backoff.spin();
+            place = end.load(Ordering::Relaxed);
+            continue;
+        }
+        match end.compare_exchange_weak(
+            place,
+            place.wrapping_add(count),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return (place, count),
+            Err(current) => {
+                #[cfg(feature = "backoff")]
+                backoff.spin();
+                place = current;
+            }
+        }
+    }
+}
+
+/// claims up to `max` consecutive published slots from `start` with a single compare-exchange,
+/// returning the first place claimed and how many were claimed
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) fn claim_read<T>(start: &AtomicUsize, slots: &[Slot<T>], max: usize) -> (usize, usize) {
+    let mut place = start.load(Ordering::Relaxed);
+    #[cfg(feature = "backoff")]
+    let mut backoff = Backoff::new();
+    loop {
+        let mut count = 0;
+        while count < max.min(slots.len())
+            && slot(slots, place.wrapping_add(count))
+                .stamp
+                .load(Ordering::Acquire)
+                == place.wrapping_add(count).wrapping_add(1)
+        {
+            count += 1;
+        }
+        if count == 0 {
+            let stamp = slot(slots, place).stamp.load(Ordering::Relaxed);
+            if max == 0 || (stamp.wrapping_sub(place.wrapping_add(1)) as isize) < 0 {
+                // the element for this place hasn't been published yet
+                return (place, 0);
+            }
+            // another consumer took this place since we loaded `start`
+            #[cfg(feature = "backoff")]
+            backoff.spin();
+            place = start.load(Ordering::Relaxed);
+            continue;
+        }
+        match start.compare_exchange_weak(
+            place,
+            place.wrapping_add(count),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return (place, count),
+            Err(current) => {
+                #[cfg(feature = "backoff")]
+                backoff.spin();
+                place = current;
+            }
+        }
+    }
+}
+
+/// claims the slot at `end` and writes `v` into it
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) fn try_insert<T>(end: &AtomicUsize, slots: &[Slot<T>], v: T) -> Result<(), T> {
+    match claim_write(end, slots, 1) {
+        (place, 1) => {
+            unsafe { slot(slots, place).publish(place, Some(v)) };
+            Ok(())
+        }
+        _ => Err(v),
+    }
+}
+
+/// claims the slot at `start` and takes the element out of it, passing over any slots that were
+/// published empty. `freed` counts the slots handed back to producers.
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) fn try_get<T>(start: &AtomicUsize, slots: &[Slot<T>], freed: &mut usize) -> Option<T> {
+    loop {
+        match claim_read(start, slots, 1) {
+            (place, 1) => {
+                *freed += 1;
+                if let Some(v) = unsafe { slot(slots, place).take(place, slots.len()) } {
+                    return Some(v);
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// how many places lie between `start` and `end`, read as a consistent pair. that includes places
+/// still being written and excludes places still being taken, so a consumer may find nothing
+/// when this is nonzero, and a producer may find no room when it's below `capacity`.
+pub(crate) fn len(start: &AtomicUsize, end: &AtomicUsize, capacity: usize) -> usize {
+    loop {
+        let before = end.load(Ordering::SeqCst);
+        let start = start.load(Ordering::SeqCst);
+        // with `end` unchanged around it, `start` was read at a moment when it was at most `end`
+        if end.load(Ordering::SeqCst) == before {
+            return before.wrapping_sub(start).min(capacity);
+        }
+    }
+}
+
+/// drops the elements in `start..end`. a slot that a producer claimed but never published, like
+/// one under a forgotten `WriteGrant`, never had a value written into it, so it's passed over.
+///
+/// # Safety
+/// nothing else may be using the slots, and they mustn't be used again afterwards
+pub(crate) unsafe fn drop_remaining<T>(start: &AtomicUsize, end: &AtomicUsize, slots: &[Slot<T>]) {
+    let start = start.load(Ordering::Relaxed);
+    let end = end.load(Ordering::Relaxed);
+    for i in 0..end.wrapping_sub(start) {
+        let place = start.wrapping_add(i);
+        let slot = slot(slots, place);
+        if slot.stamp.load(Ordering::Acquire) == place.wrapping_add(1) {
+            drop(slot.take(place, slots.len()));
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! storage for an element that stays in its slot after being read, for the rings whose readers
+//! look at elements in place rather than taking them, so that it's only dropped once it's
+//! overwritten or the ring goes away
+
+use core::mem::MaybeUninit;
+
+use crate::sync::UnsafeCell;
+
+pub(crate) struct Retained<T> {
+    /// whether `value` has been written at all, so that an element is dropped exactly once,
+    /// whether that's when it's overwritten or along with the storage
+    occupied: UnsafeCell<bool>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Retained<T> {
+    #[cfg(not(feature = "loom"))]
+    pub(crate) const fn new() -> Self {
+        Retained {
+            occupied: UnsafeCell::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    #[cfg(feature = "loom")]
+    pub(crate) fn new() -> Self {
+        Retained {
+            occupied: UnsafeCell::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// drops the element in the slot, if there is one, and moves `v` in
+    ///
+    /// # Safety
+    /// nothing else may be looking at the slot
+    pub(crate) unsafe fn replace(&self, v: T) {
+        self.occupied.with_mut(|occupied| {
+            self.value.with_mut(|value| {
+                if *occupied {
+                    (*value).assume_init_drop();
+                }
+                (*value).write(v);
+                *occupied = true;
+            })
+        });
+    }
+
+    /// # Safety
+    /// an element must have been written, and nothing may be changing it
+    pub(crate) unsafe fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.value.with(|value| f((*value).assume_init_ref()))
+    }
+
+    /// # Safety
+    /// an element must have been written, and nothing else may be looking at it
+    pub(crate) unsafe fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.value.with_mut(|value| f((*value).assume_init_mut()))
+    }
+}
+
+impl<T> Drop for Retained<T> {
+    fn drop(&mut self) {
+        self.occupied.with(|occupied| {
+            self.value.with_mut(|value| unsafe {
+                if *occupied {
+                    (*value).assume_init_drop();
+                }
+            })
+        });
+    }
+}
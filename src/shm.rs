@@ -0,0 +1,494 @@
+//! a ring buffer in shared memory, for passing plain-old-data records between processes on the
+//! same host
+
+use core::{
+    fmt,
+    mem::{align_of, size_of},
+    ptr::{addr_of_mut, NonNull},
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use std::{ffi::CString, io};
+
+use crate::{padded::CachePadded, raw, raw::Slot, sync::AtomicUsize};
+
+/// what a mapping starts with once it holds a ring
+const MAGIC: u64 = u64::from_le_bytes(*b"RINGBUF\0");
+/// bumped whenever the layout of the header or the slots changes
+const VERSION: u32 = 2;
+
+/// The start of the mapping, followed by the slots at the next multiple of their alignment.
+#[repr(C)]
+struct Header {
+    /// `MAGIC` once the creator has finished setting the mapping up, and 0 until then
+    magic: AtomicU64,
+    version: u32,
+    /// `size_of::<usize>()` in the creating process, since the counters and stamps are usizes
+    word_size: u32,
+    capacity: u64,
+    element_size: u64,
+    element_align: u64,
+    /// the alignment `start` and `end` are padded out to, which depends on the target and on
+    /// `--cfg ring_buffer_unpadded`, and with it where they and the slots are
+    counter_align: u64,
+    /// the position of the next element to be taken, plus k * capacity
+    start: CachePadded<AtomicUsize>,
+    /// the position of the next slot to be written, plus k * capacity
+    end: CachePadded<AtomicUsize>,
+}
+
+/// Types that can be read back from whatever bytes another process left in shared memory.
+///
+/// # Safety
+/// every bit pattern of `size_of::<Self>()` bytes must be a valid `Self`, so no `bool`s, `char`s,
+/// enums, references or other types with invalid values anywhere inside it. a `#[repr(C)]`
+/// struct whose fields are all `Pod` qualifies.
+///
+/// ```compile_fail
+/// # use ring_buffer::ShmRing;
+/// // a ring of u8s opened as bools could hand out a bool that is 2
+/// let ring = ShmRing::<bool>::open("/flags");
+/// ```
+pub unsafe trait Pod: Copy {}
+
+macro_rules! pod {
+    ($($t:ty)*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+pod!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize f32 f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// A [`RingBuffer`](crate::RingBuffer) laid out in memory that several processes have mapped,
+/// so that they can pass records to one another.
+///
+/// Only [`Pod`] types can go in, as nothing is ever dropped and the records are just bytes to
+/// the other side. Every process using the ring must use the same `T`, which the header only
+/// partly checks: it records the capacity and the element's size and alignment, and rejects a
+/// mapping that disagrees with them.
+pub struct ShmRing<T: Pod> {
+    header: NonNull<Header>,
+    slots: NonNull<Slot<T>>,
+    capacity: usize,
+    /// the length of the mapping to unmap when dropped, if it was mapped by `create` or `open`
+    mapped: Option<usize>,
+}
+
+unsafe impl<T: Pod + Send> Send for ShmRing<T> {}
+unsafe impl<T: Pod + Send> Sync for ShmRing<T> {}
+
+impl<T: Pod> ShmRing<T> {
+    /// how far into the mapping the slots start
+    fn slots_offset() -> usize {
+        size_of::<Header>().next_multiple_of(align_of::<Slot<T>>())
+    }
+
+    /// how many bytes a mapping must have to hold a ring of `capacity` elements, or
+    /// `ShmError::Layout` if that's more than fits in a usize
+    pub fn mapping_size(capacity: usize) -> Result<usize, ShmError> {
+        capacity
+            .checked_mul(size_of::<Slot<T>>())
+            .and_then(|slots| slots.checked_add(Self::slots_offset()))
+            .ok_or(ShmError::Layout)
+    }
+
+    /// sets up a ring of `capacity` elements at the start of a mapping and returns a handle to
+    /// it. the capacity must be a power of two greater than one, as with `RingBuffer`.
+    ///
+    /// # Safety
+    /// `ptr` must point to `len` bytes of memory that is readable and writable, that nobody else
+    /// uses until this returns, and that stays mapped for as long as the returned ring is alive
+    pub unsafe fn init_raw_mapping(
+        ptr: *mut u8,
+        len: usize,
+        capacity: usize,
+    ) -> Result<Self, ShmError> {
+        if capacity < 2
+            || !capacity.is_power_of_two()
+            || ptr.align_offset(align_of::<Header>()) != 0
+            || len < Self::mapping_size(capacity)?
+        {
+            return Err(ShmError::Layout);
+        }
+        let header = ptr as *mut Header;
+        // anyone opening the mapping meanwhile must see it isn't a ring yet
+        (*header).magic.store(0, Ordering::Relaxed);
+        addr_of_mut!((*header).version).write(VERSION);
+        addr_of_mut!((*header).word_size).write(size_of::<usize>() as u32);
+        addr_of_mut!((*header).capacity).write(capacity as u64);
+        addr_of_mut!((*header).element_size).write(size_of::<T>() as u64);
+        addr_of_mut!((*header).element_align).write(align_of::<T>() as u64);
+        addr_of_mut!((*header).counter_align).write(align_of::<CachePadded<AtomicUsize>>() as u64);
+        addr_of_mut!((*header).start).write(CachePadded::new(AtomicUsize::new(0)));
+        addr_of_mut!((*header).end).write(CachePadded::new(AtomicUsize::new(0)));
+        let slots = ptr.add(Self::slots_offset()) as *mut Slot<T>;
+        for i in 0..capacity {
+            slots.add(i).write(Slot::new(i));
+        }
+        (*header).magic.store(MAGIC, Ordering::Release);
+        Ok(ShmRing {
+            header: NonNull::new_unchecked(header),
+            slots: NonNull::new_unchecked(slots),
+            capacity,
+            mapped: None,
+        })
+    }
+
+    /// returns a handle to the ring that another handle set up at the start of a mapping,
+    /// checking that its header matches `T`.
+    ///
+    /// # Safety
+    /// `ptr` must point to `len` bytes of memory that is readable and writable, that is only
+    /// ever written by `ShmRing`s, and that stays mapped for as long as the returned ring is
+    /// alive
+    pub unsafe fn from_raw_mapping(ptr: *mut u8, len: usize) -> Result<Self, ShmError> {
+        if ptr.align_offset(align_of::<Header>()) != 0 || len < size_of::<Header>() {
+            return Err(ShmError::Layout);
+        }
+        let header = &*(ptr as *const Header);
+        // pairs with the release in `init_raw_mapping`, so the rest of the header is there
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(ShmError::NotRing);
+        }
+        if header.version != VERSION {
+            return Err(ShmError::Version(header.version));
+        }
+        let capacity = match usize::try_from(header.capacity) {
+            Ok(capacity) if capacity > 1 && capacity.is_power_of_two() => capacity,
+            _ => return Err(ShmError::Layout),
+        };
+        if header.word_size as usize != size_of::<usize>()
+            || header.element_size != size_of::<T>() as u64
+            || header.element_align != align_of::<T>() as u64
+            || header.counter_align != align_of::<CachePadded<AtomicUsize>>() as u64
+            || len < Self::mapping_size(capacity)?
+        {
+            return Err(ShmError::Layout);
+        }
+        Ok(ShmRing {
+            header: NonNull::new_unchecked(ptr as *mut Header),
+            slots: NonNull::new_unchecked(ptr.add(Self::slots_offset()) as *mut Slot<T>),
+            capacity,
+            mapped: None,
+        })
+    }
+
+    /// creates the shared memory object `name`, which mustn't exist yet, and sets up a ring of at
+    /// least `capacity` elements in it. the capacity is rounded up as for `HeapRingBuffer`.
+    pub fn create(name: &str, capacity: usize) -> Result<Self, ShmError> {
+        let capacity = capacity
+            .max(2)
+            .checked_next_power_of_two()
+            .ok_or(ShmError::Layout)?;
+        let len = Self::mapping_size(capacity)?;
+        let size = libc::off_t::try_from(len).map_err(|_| ShmError::Layout)?;
+        let name = shm_name(name)?;
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let result = unsafe {
+            if libc::ftruncate(fd, size) < 0 {
+                Err(io::Error::last_os_error().into())
+            } else {
+                map(fd, len).and_then(|ptr| {
+                    match Self::init_raw_mapping(ptr.as_ptr(), len, capacity) {
+                        Ok(mut ring) => {
+                            ring.mapped = Some(len);
+                            Ok(ring)
+                        }
+                        Err(e) => {
+                            libc::munmap(ptr.as_ptr().cast(), len);
+                            Err(e)
+                        }
+                    }
+                })
+            }
+        };
+        unsafe {
+            libc::close(fd);
+            if result.is_err() {
+                libc::shm_unlink(name.as_ptr());
+            }
+        }
+        result
+    }
+
+    /// maps the shared memory object `name`, which `create` made, and returns a handle to the
+    /// ring in it
+    pub fn open(name: &str) -> Result<Self, ShmError> {
+        let name = shm_name(name)?;
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let result = unsafe {
+            let mut stat = core::mem::zeroed::<libc::stat>();
+            if libc::fstat(fd, &mut stat) < 0 {
+                Err(io::Error::last_os_error().into())
+            } else if let Ok(len) = usize::try_from(stat.st_size) {
+                map(fd, len).and_then(|ptr| match Self::from_raw_mapping(ptr.as_ptr(), len) {
+                    Ok(mut ring) => {
+                        ring.mapped = Some(len);
+                        Ok(ring)
+                    }
+                    Err(e) => {
+                        libc::munmap(ptr.as_ptr().cast(), len);
+                        Err(e)
+                    }
+                })
+            } else {
+                Err(ShmError::Layout)
+            }
+        };
+        unsafe { libc::close(fd) };
+        result
+    }
+
+    /// removes the shared memory object `name`. rings already mapped from it carry on working,
+    /// and it's freed once the last of them is dropped.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        let name = shm_name(name)?;
+        if unsafe { libc::shm_unlink(name.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { self.header.as_ref() }
+    }
+
+    fn slots(&self) -> &[Slot<T>] {
+        unsafe { slice::from_raw_parts(self.slots.as_ptr(), self.capacity) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn try_insert(&self, v: T) -> Result<(), T> {
+        raw::try_insert(&self.header().end, self.slots(), v)
+    }
+
+    pub fn try_get(&self) -> Option<T> {
+        raw::try_get(&self.header().start, self.slots(), &mut 0)
+    }
+}
+
+impl<T: Pod> Drop for ShmRing<T> {
+    fn drop(&mut self) {
+        if let Some(len) = self.mapped {
+            unsafe { libc::munmap(self.header.as_ptr().cast(), len) };
+        }
+    }
+}
+
+fn shm_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+/// maps `len` bytes of the object behind `fd`, shared with everyone else who maps it
+unsafe fn map(fd: libc::c_int, len: usize) -> Result<NonNull<u8>, ShmError> {
+    let ptr = libc::mmap(
+        core::ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(NonNull::new_unchecked(ptr.cast()))
+}
+
+/// The error from setting up or opening a [`ShmRing`].
+#[derive(Debug)]
+pub enum ShmError {
+    Io(io::Error),
+    /// the mapping doesn't hold a ring, or its creator hasn't finished setting it up
+    NotRing,
+    /// the ring was made by a version of this crate with a different layout
+    Version(u32),
+    /// the mapping is too small or misaligned, or its ring was made for a different element
+    /// type or word size
+    Layout,
+}
+
+impl From<io::Error> for ShmError {
+    fn from(e: io::Error) -> Self {
+        ShmError::Io(e)
+    }
+}
+
+impl fmt::Display for ShmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShmError::Io(e) => write!(f, "mapping shared memory: {}", e),
+            ShmError::NotRing => f.write_str("shared memory doesn't hold a ring"),
+            ShmError::Version(v) => write!(f, "ring has layout version {}, not {}", v, VERSION),
+            ShmError::Layout => f.write_str("ring doesn't match the mapping or element type"),
+        }
+    }
+}
+
+impl std::error::Error for ShmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Record {
+        id: u32,
+        value: f64,
+    }
+
+    unsafe impl Pod for Record {}
+
+    /// stands in for a page of shared memory
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+
+    #[test]
+    fn raw_mapping_round_trip() {
+        let mut page = Box::new(Page([0; 4096]));
+        let ptr = page.0.as_mut_ptr();
+        assert!(matches!(
+            unsafe { ShmRing::<Record>::from_raw_mapping(ptr, 4096) },
+            Err(ShmError::NotRing)
+        ));
+        assert!(matches!(
+            unsafe { ShmRing::<Record>::init_raw_mapping(ptr, 4096, 6) },
+            Err(ShmError::Layout)
+        ));
+        let writer = unsafe { ShmRing::<Record>::init_raw_mapping(ptr, 4096, 4) }.unwrap();
+        let reader = unsafe { ShmRing::<Record>::from_raw_mapping(ptr, 4096) }.unwrap();
+        assert!(matches!(
+            unsafe { ShmRing::<u8>::from_raw_mapping(ptr, 4096) },
+            Err(ShmError::Layout)
+        ));
+        for id in 0..4 {
+            assert!(writer.try_insert(Record { id, value: 0.5 }).is_ok());
+        }
+        assert!(writer.try_insert(Record { id: 4, value: 0.5 }).is_err());
+        for id in 0..4 {
+            assert_eq!(reader.try_get(), Some(Record { id, value: 0.5 }));
+        }
+        assert_eq!(reader.try_get(), None);
+    }
+
+    #[test]
+    fn differently_padded_counters() {
+        let mut page = Box::new(Page([0; 4096]));
+        let ptr = page.0.as_mut_ptr();
+        drop(unsafe { ShmRing::<u64>::init_raw_mapping(ptr, 4096, 4) }.unwrap());
+        // as if made by a build whose counters sit elsewhere in the header
+        let header = ptr as *mut Header;
+        unsafe { (*header).counter_align *= 2 };
+        assert!(matches!(
+            unsafe { ShmRing::<u64>::from_raw_mapping(ptr, 4096) },
+            Err(ShmError::Layout)
+        ));
+    }
+
+    #[test]
+    fn capacity_too_big_to_map() {
+        assert!(matches!(
+            ShmRing::<u64>::mapping_size(usize::MAX / 2),
+            Err(ShmError::Layout)
+        ));
+        let mut page = Box::new(Page([0; 4096]));
+        let ptr = page.0.as_mut_ptr();
+        drop(unsafe { ShmRing::<u64>::init_raw_mapping(ptr, 4096, 4) }.unwrap());
+        // a header claiming more slots than the address space holds, which mustn't wrap
+        // around to something that looks like it fits
+        let header = ptr as *mut Header;
+        unsafe { (*header).capacity = 1 << (usize::BITS - 2) };
+        assert!(matches!(
+            unsafe { ShmRing::<u64>::from_raw_mapping(ptr, 4096) },
+            Err(ShmError::Layout)
+        ));
+        assert!(matches!(
+            ShmRing::<u64>::create("/ring-buffer-too-big", usize::MAX),
+            Err(ShmError::Layout)
+        ));
+    }
+
+    #[test]
+    fn records_between_processes() {
+        let name = format!("/ring-buffer-test-{}", std::process::id());
+        let ring = ShmRing::<Record>::create(&name, 5).unwrap();
+        assert_eq!(ring.capacity(), 8);
+        assert!(matches!(
+            ShmRing::<Record>::create(&name, 8),
+            Err(ShmError::Io(_))
+        ));
+        assert!(matches!(ShmRing::<u16>::open(&name), Err(ShmError::Layout)));
+        let opened = ShmRing::<Record>::open(&name).unwrap();
+        // the mappings stay good once the name is gone
+        ShmRing::<Record>::unlink(&name).unwrap();
+        let n = 10_000;
+        let parent = unsafe { libc::getpid() };
+        match unsafe { libc::fork() } {
+            -1 => panic!("fork: {}", io::Error::last_os_error()),
+            0 => {
+                // nothing that might take a lock from here on, since the test threads that
+                // weren't copied into this process may have been holding one
+                for id in 0..n {
+                    let record = Record {
+                        id,
+                        value: id as f64 / 2.0,
+                    };
+                    while ring.try_insert(record).is_err() {
+                        if unsafe { libc::getppid() } != parent {
+                            unsafe { libc::_exit(1) };
+                        }
+                        std::thread::yield_now();
+                    }
+                }
+                unsafe { libc::_exit(0) };
+            }
+            child => {
+                for id in 0..n {
+                    loop {
+                        if let Some(record) = opened.try_get() {
+                            assert_eq!(
+                                record,
+                                Record {
+                                    id,
+                                    value: id as f64 / 2.0
+                                }
+                            );
+                            break;
+                        }
+                        std::thread::yield_now();
+                    }
+                }
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+                assert_eq!(opened.try_get(), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! single-producer single-consumer handles, which don't need compare-exchange to claim slots
+
+use crate::{raw, sync::Ordering, RingBuffer};
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// splits the buffer into a producer and a consumer handle. with nobody else able to touch
+    /// the buffer while they exist, each side owns its counter outright and can update it with a
+    /// plain store. the slot stamps are kept up to date, so the buffer can go back to being used
+    /// through `&self` once both handles are dropped.
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        let buffer = &*self;
+        (Producer { buffer }, Consumer { buffer })
+    }
+}
+
+/// The writing half of a split [`RingBuffer`].
+pub struct Producer<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+}
+
+impl<'a, T, const N: usize> Producer<'a, T, N> {
+    /// inserts `v`, or gives it back if the buffer is full. wait-free, being a load and a store
+    /// on each side's counters, so it's safe to call from an interrupt handler that owns the
+    /// producer.
+    pub fn try_insert(&mut self, v: T) -> Result<(), T> {
+        let place = self.buffer.end.load(Ordering::Relaxed);
+        let slot = raw::slot(&self.buffer.data, place);
+        if slot.stamp.load(Ordering::Acquire) != place {
+            return Err(v);
+        }
+        unsafe { slot.publish(place, Some(v)) };
+        self.buffer
+            .end
+            .store(place.wrapping_add(1), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The reading half of a split [`RingBuffer`].
+///
+/// Sharing the consumer between threads shares the elements it peeks at, so it's only `Sync` if
+/// the element is:
+///
+/// ```compile_fail
+/// # use core::cell::Cell;
+/// # use ring_buffer::RingBuffer;
+/// fn share<S: Sync>(_: &S) {}
+/// let mut queue = RingBuffer::<Cell<u32>, 4>::new();
+/// let (_, consumer) = queue.split();
+/// share(&consumer);
+/// ```
+pub struct Consumer<'a, T, const N: usize> {
+    buffer: &'a RingBuffer<T, N>,
+}
+
+// the buffer is `Sync` whenever `T: Send`, but a shared consumer hands out `&T`
+unsafe impl<'a, T: Sync, const N: usize> Sync for Consumer<'a, T, N> {}
+
+impl<'a, T, const N: usize> Consumer<'a, T, N> {
+    /// takes the oldest element, if there is one. wait-free like `Producer::try_insert`, apart
+    /// from passing over any slots an abandoned write published empty before the split.
+    pub fn try_get(&mut self) -> Option<T> {
+        loop {
+            let place = self.buffer.start.load(Ordering::Relaxed);
+            let slot = raw::slot(&self.buffer.data, place);
+            if slot.stamp.load(Ordering::Acquire) != place.wrapping_add(1) {
+                return None;
+            }
+            let v = unsafe { slot.take(place, N) };
+            self.buffer
+                .start
+                .store(place.wrapping_add(1), Ordering::Relaxed);
+            // slots published empty by an abandoned write from before the split are skipped
+            if v.is_some() {
+                return v;
+            }
+        }
+    }
+
+    /// the oldest element, left where it is. it can't be taken or overwritten while it's
+    /// borrowed, as taking it needs the consumer mutably.
+    pub fn peek(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+    /// the element `i` places after the oldest, left where it is
+    pub fn peek_nth(&self, i: usize) -> Option<&T> {
+        self.iter().nth(i)
+    }
+
+    /// an iterator over the elements from the oldest onwards, in the order they'd be taken,
+    /// leaving them where they are. it stops at the first slot that isn't published yet, so it
+    /// may see elements the producer inserts while it's going.
+    pub fn iter(&self) -> Iter<'_, T, N> {
+        Iter {
+            buffer: self.buffer,
+            place: self.buffer.start.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The elements of a split [`RingBuffer`], borrowed from its [`Consumer`].
+///
+/// The iterator hands out `&T`, so it can only be sent to another thread if the element is
+/// `Sync`:
+///
+/// ```compile_fail
+/// # use core::cell::Cell;
+/// # use ring_buffer::RingBuffer;
+/// fn send<S: Send>(_: S) {}
+/// let mut queue = RingBuffer::<Cell<u32>, 4>::new();
+/// let (_, consumer) = queue.split();
+/// send(consumer.iter());
+/// ```
+pub struct Iter<'c, T, const N: usize> {
+    buffer: &'c RingBuffer<T, N>,
+    place: usize,
+}
+
+// as for `Consumer`'s `Sync`, since the elements it lends can end up on the other thread
+unsafe impl<'c, T: Sync, const N: usize> Send for Iter<'c, T, N> {}
+
+impl<'c, T, const N: usize> Iterator for Iter<'c, T, N> {
+    type Item = &'c T;
+
+    fn next(&mut self) -> Option<&'c T> {
+        let start = self.buffer.start.load(Ordering::Relaxed);
+        loop {
+            // the whole lap from `start` is as far as published elements can go
+            if self.place.wrapping_sub(start) >= N {
+                return None;
+            }
+            let slot = raw::slot(&self.buffer.data, self.place);
+            if slot.stamp.load(Ordering::Acquire) != self.place.wrapping_add(1) {
+                return None;
+            }
+            self.place = self.place.wrapping_add(1);
+            // the slot stays published until the consumer takes it, which it can't while this
+            // borrows it
+            unsafe {
+                if !slot.is_empty() {
+                    return Some(slot.value.with(|p| (*p).assume_init_ref()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_single_thread() {
+        let mut queue = RingBuffer::<u32, 4>::new();
+        let (mut producer, mut consumer) = queue.split();
+        for round in 0..3 {
+            for i in 0..4 {
+                assert!(producer.try_insert(round * 4 + i).is_ok());
+            }
+            assert_eq!(producer.try_insert(99), Err(99));
+            for i in 0..4 {
+                assert_eq!(consumer.try_get(), Some(round * 4 + i));
+            }
+            assert_eq!(consumer.try_get(), None);
+        }
+    }
+
+    #[test]
+    fn split_two_thread_count() {
+        let mut queue = RingBuffer::<u32, 16>::new();
+        let n = 1_000_000;
+        let (mut producer, mut consumer) = queue.split();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for x in 0..n {
+                    while producer.try_insert(x).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut x = 0;
+            while x < n {
+                if let Some(y) = consumer.try_get() {
+                    assert_eq!(y, x);
+                    x += 1;
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn peek_across_the_wrap() {
+        let mut queue = RingBuffer::<u32, 4>::new();
+        let (mut producer, mut consumer) = queue.split();
+        assert_eq!(consumer.peek(), None);
+        for x in 0..3 {
+            assert!(producer.try_insert(x).is_ok());
+        }
+        assert_eq!(consumer.try_get(), Some(0));
+        assert_eq!(consumer.try_get(), Some(1));
+        for x in 3..6 {
+            assert!(producer.try_insert(x).is_ok());
+        }
+        assert_eq!(consumer.peek(), Some(&2));
+        assert_eq!(consumer.peek_nth(3), Some(&5));
+        assert_eq!(consumer.peek_nth(4), None);
+        assert!(consumer.iter().copied().eq(2..6));
+        // peeking leaves everything in place
+        assert_eq!(producer.try_insert(6), Err(6));
+        assert_eq!(consumer.try_get(), Some(2));
+        assert!(consumer.iter().copied().eq(3..6));
+    }
+
+    #[test]
+    fn iter_skips_abandoned_writes() {
+        let mut queue = RingBuffer::<u32, 4>::new();
+        assert!(queue.try_insert(1).is_ok());
+        drop(queue.try_reserve());
+        assert!(queue.try_insert(2).is_ok());
+        let (_, mut consumer) = queue.split();
+        assert_eq!(consumer.peek_nth(1), Some(&2));
+        assert!(consumer.iter().copied().eq([1, 2]));
+        assert_eq!(consumer.try_get(), Some(1));
+        assert_eq!(consumer.peek(), Some(&2));
+    }
+
+    #[test]
+    fn peek_while_producing() {
+        let mut queue = RingBuffer::<u32, 16>::new();
+        let n = 100_000;
+        let (mut producer, mut consumer) = queue.split();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for x in 0..n {
+                    while producer.try_insert(x).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut x = 0;
+            while x < n {
+                // whatever is visible is the next run of elements, in order
+                let seen = consumer.iter().count() as u32;
+                assert!(consumer.iter().copied().take(seen as usize).eq(x..x + seen));
+                match consumer.peek() {
+                    Some(&y) => {
+                        assert_eq!(y, x);
+                        assert_eq!(consumer.try_get(), Some(x));
+                        x += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn shared_use_after_split() {
+        let mut queue = RingBuffer::<u32, 4>::new();
+        assert!(queue.try_insert(1).is_ok());
+        {
+            let (mut producer, mut consumer) = queue.split();
+            assert_eq!(consumer.try_get(), Some(1));
+            assert!(producer.try_insert(2).is_ok());
+            assert!(producer.try_insert(3).is_ok());
+        }
+        assert_eq!(queue.try_get(), Some(2));
+        assert!(queue.try_insert(4).is_ok());
+        assert!(queue.try_insert(5).is_ok());
+        assert!(queue.try_insert(6).is_ok());
+        assert!(queue.try_insert(7).is_err());
+        let (_, mut consumer) = queue.split();
+        for x in 3..7 {
+            assert_eq!(consumer.try_get(), Some(x));
+        }
+        assert_eq!(consumer.try_get(), None);
+    }
+}
@@ -0,0 +1,38 @@
+//! the atomics, fences, cells and spin hint used by the buffers, swapped for loom's versions
+//! when model checking
+
+#[cfg(feature = "loom")]
+pub(crate) use loom::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+#[cfg(not(feature = "loom"))]
+pub(crate) use core::sync::atomic::{AtomicUsize, Ordering};
+
+// only the buffers built on compare-exchange spin or fence
+#[cfg(all(not(feature = "loom"), target_has_atomic = "ptr"))]
+pub(crate) use core::{hint::spin_loop, sync::atomic::fence};
+
+/// `core::cell::UnsafeCell` behind the same closure-based interface as loom's, so that loom can
+/// track every access
+#[cfg(not(feature = "loom"))]
+#[derive(Debug)]
+#[repr(transparent)]
+pub(crate) struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(feature = "loom"))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(data: T) -> Self {
+        UnsafeCell(core::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
@@ -0,0 +1,431 @@
+#![cfg(feature = "loom")]
+
+use loom::{model::Builder, sync::Arc, thread};
+use ring_buffer::{Broadcast, ByteRing, Pipeline, RingBuffer, TryGetError};
+
+/// runs `f` under loom through every interleaving, which is quick enough for the models with
+/// up to three threads
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    Builder::new().check(f);
+}
+
+/// runs `f` under loom with at most `preemptions` preemptions, unless `LOOM_MAX_PREEMPTIONS`
+/// says otherwise. the models that spawn four threads are run with a bound of 2: it takes them a
+/// few seconds to a minute and a half, where without one even `wraparound` is still going after
+/// ten minutes.
+fn model_bounded(preemptions: usize, f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = Builder::new();
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(preemptions);
+    }
+    builder.check(f);
+}
+
+/// spawns one thread per value, each trying once to insert it, and returns the values that made
+/// it into the queue
+fn spawn_producers<const N: usize>(
+    queue: &Arc<RingBuffer<u32, N>>,
+    values: &[u32],
+) -> Vec<thread::JoinHandle<Option<u32>>> {
+    values
+        .iter()
+        .map(|&v| {
+            let queue = queue.clone();
+            thread::spawn(move || queue.try_insert(v).ok().map(|()| v))
+        })
+        .collect()
+}
+
+/// spawns `count` threads that each try `tries` times to take an element
+fn spawn_consumers<const N: usize>(
+    queue: &Arc<RingBuffer<u32, N>>,
+    count: usize,
+    tries: usize,
+) -> Vec<thread::JoinHandle<Vec<u32>>> {
+    (0..count)
+        .map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || (0..tries).filter_map(|_| queue.try_get()).collect())
+        })
+        .collect()
+}
+
+/// joins everything and checks that each inserted value came out exactly once, returning what
+/// the producers managed to insert and what each consumer took
+fn check_conserved<const N: usize>(
+    queue: &RingBuffer<u32, N>,
+    already_inserted: Vec<u32>,
+    producers: Vec<thread::JoinHandle<Option<u32>>>,
+    consumers: Vec<thread::JoinHandle<Vec<u32>>>,
+) -> (Vec<u32>, Vec<Vec<u32>>) {
+    let produced: Vec<u32> = producers
+        .into_iter()
+        .filter_map(|p| p.join().unwrap())
+        .collect();
+    let taken: Vec<Vec<u32>> = consumers.into_iter().map(|c| c.join().unwrap()).collect();
+    let mut inserted = already_inserted;
+    inserted.extend(&produced);
+    let mut received: Vec<u32> = taken.iter().flatten().copied().collect();
+    received.extend(queue.drain());
+    inserted.sort();
+    received.sort();
+    assert_eq!(inserted, received);
+    (produced, taken)
+}
+
+#[test]
+fn one_producer_one_consumer() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                assert!(queue.try_insert(1).is_ok());
+                assert!(queue.try_insert(2).is_ok());
+            })
+        };
+        let mut received = vec![];
+        while received.len() < 2 {
+            match queue.try_get() {
+                Some(v) => received.push(v),
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(received, [1, 2]);
+    });
+}
+
+#[test]
+fn two_producers_stall_independently() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        let producers: Vec<_> = (0..2)
+            .map(|i| {
+                let queue = queue.clone();
+                thread::spawn(move || assert!(queue.try_insert(i).is_ok()))
+            })
+            .collect();
+        let mut sum = 0;
+        let mut received = 0;
+        while received < 2 {
+            match queue.try_get() {
+                Some(v) => {
+                    sum += v;
+                    received += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(sum, 1);
+        assert!(queue.try_get().is_none());
+    });
+}
+
+#[test]
+fn two_producers_two_consumers() {
+    model_bounded(2, || {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        let producers = spawn_producers(&queue, &[1, 2]);
+        let consumers = spawn_consumers(&queue, 2, 1);
+        check_conserved(&queue, vec![], producers, consumers);
+    });
+}
+
+#[test]
+fn three_producers_one_consumer() {
+    model_bounded(2, || {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        let producers = spawn_producers(&queue, &[1, 2, 3]);
+        let consumers = spawn_consumers(&queue, 1, 2);
+        check_conserved(&queue, vec![], producers, consumers);
+    });
+}
+
+#[test]
+fn one_producer_three_consumers() {
+    model_bounded(2, || {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        let producers = spawn_producers(&queue, &[2]);
+        let consumers = spawn_consumers(&queue, 3, 1);
+        check_conserved(&queue, vec![1], producers, consumers);
+    });
+}
+
+#[test]
+fn full_buffer() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let producers = spawn_producers(&queue, &[3, 4]);
+        let consumers = spawn_consumers(&queue, 1, 1);
+        let (produced, taken) = check_conserved(&queue, vec![1, 2], producers, consumers);
+        // a producer can only get in once the oldest element has been taken, and then only one
+        assert!(produced.len() <= taken[0].len());
+        assert!(taken[0].iter().all(|&v| v == 1));
+    });
+}
+
+#[test]
+fn full_buffer_two_consumers() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let producers = spawn_producers(&queue, &[3]);
+        let consumers = spawn_consumers(&queue, 2, 1);
+        check_conserved(&queue, vec![1, 2], producers, consumers);
+    });
+}
+
+#[test]
+fn wraparound() {
+    model_bounded(2, || {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        // move start and end onto the last slot, so the next two places span the wrap
+        assert!(queue.try_insert(0).is_ok());
+        assert_eq!(queue.try_get(), Some(0));
+        assert!(queue.try_insert(1).is_ok());
+        let producers = spawn_producers(&queue, &[2, 3]);
+        let consumers = spawn_consumers(&queue, 2, 1);
+        check_conserved(&queue, vec![1], producers, consumers);
+    });
+}
+
+#[test]
+fn wraparound_preserves_order() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(0).is_ok());
+        assert_eq!(queue.try_get(), Some(0));
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for v in 1..4 {
+                    if queue.try_insert(v).is_err() {
+                        return v;
+                    }
+                }
+                4
+            })
+        };
+        let consumers = spawn_consumers(&queue, 1, 2);
+        let sent_up_to = producer.join().unwrap();
+        let mut received = consumers.into_iter().next().unwrap().join().unwrap();
+        received.extend(queue.drain());
+        assert_eq!(received, (1..sent_up_to).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn batch_insert_and_get() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 4>::new());
+        assert!(queue.try_insert(0).is_ok());
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.try_insert_slice(&[1, 2, 3, 4]))
+        };
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.drain_up_to(2).collect::<Vec<_>>())
+        };
+        let inserted = producer.join().unwrap();
+        let mut received = consumer.join().unwrap();
+        received.extend(queue.drain());
+        assert_eq!(received, (0..inserted as u32 + 1).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn read_grant_held_across_other_consumer() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.try_peek_grant().map(|grant| *grant))
+        };
+        let other = queue.try_get();
+        let refill = queue.try_insert(3).is_ok();
+        let peeked = consumer.join().unwrap();
+        let mut received: Vec<_> = peeked.into_iter().chain(other).collect();
+        received.sort();
+        assert_eq!(received, [1, 2]);
+        assert_eq!(
+            queue.drain().collect::<Vec<_>>(),
+            if refill { vec![3] } else { vec![] }
+        );
+    });
+}
+
+#[test]
+fn byte_stream_across_the_wrap() {
+    model(|| {
+        let ring: &'static mut ByteRing<4> = Box::leak(Box::new(ByteRing::new()));
+        let (mut writer, mut reader) = ring.split();
+        let writer = thread::spawn(move || {
+            let mut sent = 0;
+            for chunk in [[1, 2, 3], [4, 5, 6]] {
+                sent += writer.write(&chunk[..]);
+            }
+            sent
+        });
+        let mut received = Vec::new();
+        let mut buf = [0; 4];
+        for _ in 0..2 {
+            let n = reader.read(&mut buf);
+            received.extend_from_slice(&buf[..n]);
+        }
+        let sent = writer.join().unwrap();
+        loop {
+            let n = reader.read(&mut buf);
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, [1, 2, 3, 4, 5, 6][..sent]);
+    });
+}
+
+#[test]
+fn byte_region_skips_the_wrap() {
+    model(|| {
+        let ring: &'static mut ByteRing<4> = Box::leak(Box::new(ByteRing::new()));
+        let (mut writer, mut reader) = ring.split();
+        // one byte left before the wrap, and the reader freeing the beginning as it goes
+        assert_eq!(writer.write(&[1, 2, 3]), 3);
+        assert_eq!(reader.read(&mut [0; 2]), 2);
+        let writer = thread::spawn(move || {
+            let region = writer.write_slice();
+            let len = region.len();
+            for (i, byte) in region.iter_mut().enumerate() {
+                *byte = 4 + i as u8;
+            }
+            writer.commit(len);
+            len
+        });
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let region = reader.read_slice();
+            received.extend_from_slice(region);
+            let len = region.len();
+            reader.consume(len);
+        }
+        let written = writer.join().unwrap();
+        let mut buf = [0; 4];
+        let n = reader.read(&mut buf);
+        received.extend_from_slice(&buf[..n]);
+        assert_eq!(received, [3, 4, 5, 6][..1 + written]);
+    });
+}
+
+#[test]
+fn force_insert_against_consumer() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.try_get())
+        };
+        let evicted = queue.force_insert(3);
+        let taken = consumer.join().unwrap();
+        assert_eq!(evicted.is_some() as usize, queue.overwritten());
+        let mut seen: Vec<_> = evicted
+            .into_iter()
+            .chain(taken)
+            .chain(queue.drain())
+            .collect();
+        seen.sort();
+        assert_eq!(seen, [1, 2, 3]);
+    });
+}
+
+#[test]
+fn force_inserts_race_for_the_oldest() {
+    model(|| {
+        let queue = Arc::new(RingBuffer::<u32, 2>::new());
+        assert!(queue.try_insert(1).is_ok());
+        assert!(queue.try_insert(2).is_ok());
+        let other = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.force_insert(4))
+        };
+        let mut evicted: Vec<_> = queue.force_insert(3).into_iter().collect();
+        evicted.extend(other.join().unwrap());
+        // each call evicted exactly one element, and they were the two oldest
+        evicted.sort();
+        assert_eq!(evicted, [1, 2]);
+        assert_eq!(queue.overwritten(), 2);
+        let mut left: Vec<_> = queue.drain().collect();
+        left.sort();
+        assert_eq!(left, [3, 4]);
+    });
+}
+
+#[test]
+fn lossy_broadcast_overwrites_under_reader() {
+    model(|| {
+        let broadcast: &'static Broadcast<u32, 2, 1> = Box::leak(Box::new(Broadcast::new_lossy()));
+        let mut reader = broadcast.subscribe().unwrap();
+        let writer = thread::spawn(move || {
+            for v in 0..3 {
+                assert!(broadcast.try_insert(v).is_ok());
+            }
+        });
+        let mut next = 0;
+        for _ in 0..2 {
+            match reader.try_get() {
+                Ok(v) => {
+                    assert_eq!(v, next);
+                    next += 1;
+                }
+                Err(TryGetError::Lagged(missed)) => next += missed as u32,
+                Err(TryGetError::Empty) => {}
+            }
+        }
+        writer.join().unwrap();
+        while let Ok(v) = reader.try_get() {
+            assert_eq!(v, next);
+            next += 1;
+        }
+        assert!(next <= 3);
+    });
+}
+
+#[test]
+fn pipeline_stages_in_place() {
+    model(|| {
+        let pipeline: &'static mut Pipeline<u32, 2, 2> =
+            Box::leak(Box::new(Pipeline::new([&[], &[0]])));
+        let (mut publisher, [mut double, mut collect]) = pipeline.split();
+        let publisher = thread::spawn(move || {
+            (0..3)
+                .take_while(|&v| publisher.try_publish(v).is_ok())
+                .count()
+        });
+        let double = thread::spawn(move || {
+            let mut done = 0;
+            for _ in 0..2 {
+                done += double.process_available(|v| *v *= 2);
+            }
+            done
+        });
+        let mut seen = Vec::new();
+        collect.inspect_available(|&v| seen.push(v));
+        let published = publisher.join().unwrap();
+        let doubled = double.join().unwrap();
+        collect.inspect_available(|&v| seen.push(v));
+        assert_eq!(seen, (0..doubled as u32).map(|v| v * 2).collect::<Vec<_>>());
+        assert!(doubled <= published);
+    });
+}
@@ -0,0 +1,85 @@
+//! uses only what is left of the crate on targets without compare-exchange, like thumbv6m and
+//! riscv32imc, so that the fallbacks get run on the host too, as CI does with
+//! `cargo test --test no_cas --no-default-features`. CI also builds the crate itself for those
+//! targets.
+
+#![cfg(not(feature = "loom"))]
+
+use std::{sync::Mutex, thread};
+
+use ring_buffer::{CriticalSection, IsrRingBuffer, RingBuffer};
+
+/// stands in for masking interrupts on a single-core target. none of the code here nests
+/// critical sections, so a plain mutex will do.
+struct MockCriticalSection;
+
+static MASKED: Mutex<()> = Mutex::new(());
+
+unsafe impl CriticalSection for MockCriticalSection {
+    fn with<R>(f: impl FnOnce() -> R) -> R {
+        let _masked = MASKED.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+}
+
+#[test]
+fn load_store_spsc() {
+    let mut queue = RingBuffer::<u32, 8>::new();
+    let (mut producer, mut consumer) = queue.split();
+    let n = 100_000;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for x in 0..n {
+                while producer.try_insert(x).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut x = 0;
+        while x < n {
+            match consumer.try_get() {
+                Some(y) => {
+                    assert_eq!(y, x);
+                    x += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+    });
+}
+
+#[test]
+fn critical_section_mpmc() {
+    let queue = IsrRingBuffer::<u64, 4, MockCriticalSection>::new();
+    let n = 5_000;
+    let total = Mutex::new(0);
+    thread::scope(|scope| {
+        for p in 0..3 {
+            let queue = &queue;
+            scope.spawn(move || {
+                for i in 0..n {
+                    while queue.try_insert(i * 3 + p).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        for _ in 0..3 {
+            scope.spawn(|| {
+                let mut taken = 0;
+                while taken < n {
+                    match queue.try_get() {
+                        Some(v) => {
+                            *total.lock().unwrap() += v;
+                            taken += 1;
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+    let n = 3 * n;
+    assert_eq!(total.into_inner().unwrap(), n * (n - 1) / 2);
+    assert!(queue.is_empty());
+}